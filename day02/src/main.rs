@@ -10,7 +10,7 @@ fn main(input: &str) -> isize {
     vm.write(1, 12);
     vm.write(2, 2);
 
-    vm.run();
+    vm.run().unwrap();
 
     vm.read(0)
 }
@@ -1,22 +1,17 @@
 #[macro_use] extern crate aoc;
 
+use intcode::symbolic::{Solver, Target};
+
 #[aoc(2019, 02, 2)]
 fn main(input: &str) -> isize {
     let mem = intcode::parse(input);
 
-    for noun in 0..=99 {
-        for verb in 0..=99 {
-            let mut vm = intcode::VM::new(mem.clone());
-            vm.write(1, noun);
-            vm.write(2, verb);
-
-            vm.run();
+    let solver = Solver::new(mem)
+        .symbolic_memory(1, 0..=99)
+        .symbolic_memory(2, 0..=99);
 
-            if vm.read(0) == 19690720 {
-                return 100 * noun + verb;
-            }
-        }
+    match solver.solve(Target::Memory(0), 19690720).unwrap() {
+        Some(solution) => 100 * solution[0] + solution[1],
+        None => unreachable!(),
     }
-
-    unreachable!()
 }
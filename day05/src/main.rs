@@ -6,7 +6,7 @@ fn main(input: &str) -> isize {
 
     let mut vm = intcode::VM::new(mem);
     vm.add_input(1);
-    vm.run();
+    vm.run().unwrap();
 
     let outputs = vm.outputs();
 
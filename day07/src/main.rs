@@ -1,6 +1,7 @@
 #[macro_use] extern crate aoc;
 
 use itertools::Itertools;
+use intcode::pipeline::Pipeline;
 
 #[aoc(2019, 07, 1)]
 fn main(input: &str) -> isize {
@@ -14,19 +15,9 @@ fn main(input: &str) -> isize {
 }
 
 fn run_series(mem: &[isize], phases: Vec<isize>) -> isize {
-    let mut signal = 0;
-
-    for phase in phases {
-        signal = run_stage(mem.clone(), phase, signal);
-    }
-
-    signal
-}
-
-fn run_stage(mem: &[isize], phase: isize, signal: isize) -> isize {
-    let mut vm = intcode::VM::new(mem);
-    vm.add_input(phase);
-    vm.add_input(signal);
-    vm.run();
-    vm.outputs()[0]
+    Pipeline::with_phases(mem, &phases)
+        .run(&[0])
+        .unwrap()
+        .output
+        .unwrap()
 }
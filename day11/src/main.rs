@@ -1,54 +1,70 @@
 #[macro_use] extern crate aoc;
 
 use std::collections::HashMap;
-use intcode::{VM, Op};
+use intcode::{VM, Observer};
 
 #[aoc(2019, 11, 1)]
 fn main(input: &str) -> usize {
     let mem = intcode::parse(input);
     let mut vm = VM::new(mem);
 
-    let mut hull = HashMap::new();
-    let mut direction = Direction::Up;
-    let mut x = 0;
-    let mut y = 0;
-    let mut next_output = NextOutput::Color;
-
-    vm.run_tracing(|vm, prev, next| {
-        if prev.op() == Op::WriteOutput {
-            let output = vm.outputs().last().unwrap();
-
-            match next_output {
-                NextOutput::Color => {
-                    let is_white = match output {
-                        0 => false,
-                        1 => true,
-                        _ => panic!("invalid color {}", output),
-                    };
-
-                    hull.insert((x, y), is_white);
-                    next_output = NextOutput::Direction;
-                },
-                NextOutput::Direction => {
-                    match output {
-                        0 => direction.turn_left(),
-                        1 => direction.turn_right(),
-                        _ => panic!("invalid turn {}", output)
-                    }
-
-                    direction.go(&mut x, &mut y);
-                    next_output = NextOutput::Color;
-                },
-            }
+    let mut painter = HullPainter::new();
+
+    vm.run_observed(&mut painter).unwrap();
+
+    painter.hull.values().count()
+}
+
+struct HullPainter {
+    hull: HashMap<(i32, i32), bool>,
+    direction: Direction,
+    x: i32,
+    y: i32,
+    next_output: NextOutput,
+}
+
+impl HullPainter {
+    fn new() -> Self {
+        Self {
+            hull: HashMap::new(),
+            direction: Direction::Up,
+            x: 0,
+            y: 0,
+            next_output: NextOutput::Color,
         }
+    }
+}
+
+impl Observer for HullPainter {
+    fn on_output(&mut self, _vm: &mut VM, output: isize) {
+        match self.next_output {
+            NextOutput::Color => {
+                let is_white = match output {
+                    0 => false,
+                    1 => true,
+                    _ => panic!("invalid color {}", output),
+                };
 
-        if next.op() == Op::ReadInput {
-            let color = hull.get(&(x, y)).copied().unwrap_or(false) as isize;
-            vm.add_input(color);
+                self.hull.insert((self.x, self.y), is_white);
+                self.next_output = NextOutput::Direction;
+            },
+            NextOutput::Direction => {
+                match output {
+                    0 => self.direction.turn_left(),
+                    1 => self.direction.turn_right(),
+                    _ => panic!("invalid turn {}", output)
+                }
+
+                self.direction.go(&mut self.x, &mut self.y);
+                self.next_output = NextOutput::Color;
+            },
         }
-    });
+    }
 
-    hull.values().count()
+    fn on_input_needed(&mut self, vm: &mut VM) {
+        let color = self.hull.get(&(self.x, self.y)).copied().unwrap_or(false) as isize;
+        vm.queue_input(color);
+    }
 }
 
 enum NextOutput {
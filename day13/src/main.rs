@@ -7,7 +7,7 @@ fn main(input: &str) -> usize {
     let mem = intcode::parse(input);
     let mut vm = intcode::VM::new(mem);
 
-    vm.run();
+    vm.run().unwrap();
 
     let mut field = HashMap::new();
 
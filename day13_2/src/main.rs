@@ -1,7 +1,7 @@
 #[macro_use] extern crate aoc;
 
 use std::collections::HashMap;
-use intcode::Op;
+use intcode::{VM, Observer};
 use std::cmp::Ordering;
 use termion::{clear, cursor};
 
@@ -12,58 +12,72 @@ fn main(input: &str) -> isize {
     // free play
     mem[0] = 2;
 
-    let mut vm = intcode::VM::new(mem);
-    let mut xy = (None, None);
-    let mut field = HashMap::new();
-    let mut score = 0;
-    let mut ball_x = 0;
-    let mut paddle_x = 0;
-
-    vm.run_tracing(|vm, prev, next| {
-        if prev.op() == Op::WriteOutput {
-            let value = *vm.outputs().last().unwrap();
-
-            xy = match xy {
-                (None, None) => (Some(value), None),
-                (Some(x), None) => (Some(x), Some(value)),
-                (Some(-1), Some(0)) => {
-                    score = value;
-                    (None, None)
-                },
-                (Some(x), Some(y)) => {
-                    // paddle
-                    if value == 3 {
-                        paddle_x = x;
-                    }
-
-                    // ball
-                    if value == 4 {
-                        ball_x = x;
-                    }
-
-                    field.insert((x, y), value);
-                    (None, None)
-                },
-                _ => unreachable!()
-            }
-        }
+    let mut vm = VM::new(mem);
+    let mut arcade = Arcade::new();
 
-        if next.op() == Op::ReadInput {
-            render_field(&field, score);
+    vm.run_observed(&mut arcade).unwrap();
 
-            let joy = match paddle_x.cmp(&ball_x) {
-                Ordering::Less => 1,
-                Ordering::Greater => -1,
-                Ordering::Equal => 0,
-            };
+    arcade.score
+}
+
+struct Arcade {
+    xy: (Option<isize>, Option<isize>),
+    field: HashMap<(isize, isize), isize>,
+    score: isize,
+    ball_x: isize,
+    paddle_x: isize,
+}
+
+impl Arcade {
+    fn new() -> Self {
+        Self {
+            xy: (None, None),
+            field: HashMap::new(),
+            score: 0,
+            ball_x: 0,
+            paddle_x: 0,
+        }
+    }
+}
 
-            vm.queue_input(joy);
+impl Observer for Arcade {
+    fn on_output(&mut self, _vm: &mut VM, value: isize) {
+        self.xy = match self.xy {
+            (None, None) => (Some(value), None),
+            (Some(x), None) => (Some(x), Some(value)),
+            (Some(-1), Some(0)) => {
+                self.score = value;
+                (None, None)
+            },
+            (Some(x), Some(y)) => {
+                // paddle
+                if value == 3 {
+                    self.paddle_x = x;
+                }
+
+                // ball
+                if value == 4 {
+                    self.ball_x = x;
+                }
+
+                self.field.insert((x, y), value);
+                (None, None)
+            },
+            _ => unreachable!()
         }
-    });
+    }
+
+    fn on_input_needed(&mut self, vm: &mut VM) {
+        render_field(&self.field, self.score);
 
-    vm.run();
+        let joy = match self.paddle_x.cmp(&self.ball_x) {
+            Ordering::Less => 1,
+            Ordering::Greater => -1,
+            Ordering::Equal => 0,
+        };
 
-    score
+        vm.queue_input(joy);
+    }
 }
 
 fn render_field(field: &HashMap<(isize, isize), isize>, score: isize) {
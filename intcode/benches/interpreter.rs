@@ -0,0 +1,106 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use intcode::{VM, FastVM};
+use intcode::asm::assemble;
+
+const ITERATIONS: usize = 20;
+
+// Example amplifier program from day 7
+const AMPLIFIER: [isize; 34] = [
+    3, 31, 3, 32, 1002, 32, 10, 32, 1001, 31, -2, 31, 1007, 31, 0, 33,
+    1002, 33, 7, 33, 1, 33, 31, 31, 1, 32, 31, 31, 4, 31, 99, 0, 0, 0,
+];
+
+fn main() {
+    let countdown = assemble("
+        loop:
+            add [counter], #-1, [counter]
+            mul [counter], #3, [scratch]
+            jt [counter], #loop
+            hlt
+        counter: .data 200000
+        scratch: .data 0
+    ").unwrap();
+
+    compare("countdown", || {
+        let mut vm = VM::new(countdown.clone());
+        vm.run().unwrap();
+        vm.read(0)
+    }, || {
+        let mut vm = FastVM::new(countdown.clone());
+        vm.run().unwrap();
+        vm.read(0)
+    });
+
+    compare("amplifier permutations", || {
+        permutations(&[0, 1, 2, 3, 4])
+            .into_iter()
+            .map(|phases| phases.iter().fold(0, |signal, &phase| {
+                let mut vm = VM::new(&AMPLIFIER[..]);
+                vm.add_input(phase);
+                vm.add_input(signal);
+                vm.run().unwrap();
+                vm.outputs()[0]
+            }))
+            .max()
+            .unwrap()
+    }, || {
+        permutations(&[0, 1, 2, 3, 4])
+            .into_iter()
+            .map(|phases| phases.iter().fold(0, |signal, &phase| {
+                let mut vm = FastVM::new(&AMPLIFIER[..]);
+                vm.add_input(phase);
+                vm.add_input(signal);
+                vm.run().unwrap();
+                vm.outputs()[0]
+            }))
+            .max()
+            .unwrap()
+    });
+}
+
+fn compare(name: &str, mut vm: impl FnMut() -> isize, mut fast: impl FnMut() -> isize) {
+    assert_eq!(vm(), fast(), "{}: interpreters disagree", name);
+
+    let vm_time = measure(&mut vm);
+    let fast_time = measure(&mut fast);
+
+    println!(
+        "{:<24} VM::run {:>10.3?}  FastVM::run {:>10.3?}  speedup {:.1}x",
+        name,
+        vm_time,
+        fast_time,
+        vm_time.as_secs_f64() / fast_time.as_secs_f64(),
+    );
+}
+
+fn measure(f: &mut impl FnMut() -> isize) -> Duration {
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+
+    start.elapsed() / ITERATIONS as u32
+}
+
+fn permutations(items: &[isize]) -> Vec<Vec<isize>> {
+    if items.len() <= 1 {
+        return vec![items.to_vec()];
+    }
+
+    let mut result = Vec::new();
+
+    for (index, &item) in items.iter().enumerate() {
+        let mut rest = items.to_vec();
+        rest.remove(index);
+
+        for mut permutation in permutations(&rest) {
+            permutation.insert(0, item);
+            result.push(permutation);
+        }
+    }
+
+    result
+}
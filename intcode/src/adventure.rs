@@ -0,0 +1,682 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{VM, VmError, RunState, Limit, AdventureError};
+
+const DEFAULT_BUDGET: usize = 10_000_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    Waiting,
+    Halted,
+    // The program ran out of its instruction budget without asking for input
+    Stuck,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    pub text: String,
+    pub status: Status,
+}
+
+pub trait Console: Sized {
+    fn send(&mut self, line: &str);
+    fn poll(&mut self) -> Result<Reply, AdventureError>;
+    fn fork(&mut self) -> Self;
+}
+
+pub struct IntcodeConsole<'a> {
+    vm: VM<'a>,
+    budget: usize,
+}
+
+impl<'a> IntcodeConsole<'a> {
+    pub fn new(vm: VM<'a>) -> Self {
+        Self {
+            vm,
+            budget: DEFAULT_BUDGET,
+        }
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+    }
+}
+
+impl Console for IntcodeConsole<'_> {
+    fn send(&mut self, line: &str) {
+        for byte in line.bytes().chain(Some(b'\n')) {
+            self.vm.queue_input(byte as isize);
+        }
+    }
+
+    fn poll(&mut self) -> Result<Reply, AdventureError> {
+        let mut text = String::new();
+
+        let budget = self.budget as u64;
+        let start = self.vm.instructions_executed();
+        let status = self.vm.with_instruction_budget(budget, |vm| read_reply(vm, &mut text));
+
+        // Only running out of the budget means stuck, a limit the caller set
+        // on the VM is still an error
+        let status = match status {
+            Err(VmError::LimitExceeded { limit: Limit::Instructions, .. })
+                if self.vm.instructions_executed() >= start + budget => Status::Stuck,
+            status => status?,
+        };
+
+        Ok(Reply { text, status })
+    }
+
+    fn fork(&mut self) -> Self {
+        Self {
+            vm: self.vm.fork(),
+            budget: self.budget,
+        }
+    }
+}
+
+fn read_reply(vm: &mut VM, text: &mut String) -> Result<Status, VmError> {
+    loop {
+        match vm.run_until_blocked() {
+            Ok(RunState::Output(value)) if (0..=127).contains(&value) => text.push(value as u8 as char),
+            Ok(RunState::Output(value)) => *text += &value.to_string(),
+            Ok(RunState::NeedsInput) => return Ok(Status::Waiting),
+            Ok(RunState::Halted) => return Ok(Status::Halted),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Room {
+    pub name: String,
+    pub description: String,
+    pub doors: Vec<String>,
+    pub items: Vec<String>,
+}
+
+// Parses the last room description in `text`. Getting ejected from a
+// checkpoint prints two rooms, and the last one is where we ended up.
+pub fn parse_room(text: &str) -> Option<Room> {
+    let start = text.rfind("\n== ").map(|index| index + 1).or_else(|| {
+        if text.starts_with("== ") { Some(0) } else { None }
+    })?;
+
+    let mut lines = text[start..].lines();
+    let name = lines.next()?.trim().trim_start_matches("== ").trim_end_matches(" ==").to_owned();
+
+    let mut description = Vec::new();
+    let mut doors = Vec::new();
+    let mut items = Vec::new();
+    let mut section = "";
+
+    for line in lines {
+        let line = line.trim();
+
+        match (line, line.strip_prefix("- ")) {
+            ("Command?", _) => break,
+            ("", _) => section = "",
+            (_, Some(entry)) if section == "Doors here lead:" => doors.push(entry.to_owned()),
+            (_, Some(entry)) if section == "Items here:" => items.push(entry.to_owned()),
+            ("Doors here lead:", _) | ("Items here:", _) => section = line,
+            _ if doors.is_empty() && items.is_empty() => description.push(line),
+            _ => {},
+        }
+    }
+
+    Some(Room {
+        name,
+        description: description.join("\n"),
+        doors,
+        items,
+    })
+}
+
+pub fn opposite(door: &str) -> &str {
+    match door {
+        "north" => "south",
+        "south" => "north",
+        "east" => "west",
+        "west" => "east",
+        other => other,
+    }
+}
+
+pub struct Adventure<C> {
+    console: C,
+    room: Option<Room>,
+    rooms: HashMap<String, Room>,
+    edges: HashMap<(String, String), String>,
+    explored: HashSet<String>,
+    dangerous: HashSet<String>,
+    inventory: Vec<String>,
+    checkpoint: Option<(String, String)>,
+    transcript: Vec<String>,
+}
+
+impl<C: Console> Adventure<C> {
+    pub fn new(console: C) -> Self {
+        Self {
+            console,
+            room: None,
+            rooms: HashMap::new(),
+            edges: HashMap::new(),
+            explored: HashSet::new(),
+            dangerous: HashSet::new(),
+            inventory: Vec::new(),
+            checkpoint: None,
+            transcript: Vec::new(),
+        }
+    }
+
+    pub fn room(&self) -> Option<&Room> {
+        self.room.as_ref()
+    }
+
+    pub fn rooms(&self) -> &HashMap<String, Room> {
+        &self.rooms
+    }
+
+    pub fn inventory(&self) -> &[String] {
+        &self.inventory
+    }
+
+    pub fn transcript(&self) -> &[String] {
+        &self.transcript
+    }
+
+    pub fn checkpoint(&self) -> Option<&(String, String)> {
+        self.checkpoint.as_ref()
+    }
+
+    pub fn avoid(&mut self, item: &str) {
+        self.dangerous.insert(item.to_owned());
+    }
+
+    pub fn start(&mut self) -> Result<String, AdventureError> {
+        let reply = self.console.poll()?;
+        self.handle(None, reply)
+    }
+
+    pub fn command(&mut self, command: &str) -> Result<String, AdventureError> {
+        self.console.send(command);
+        let reply = self.console.poll()?;
+        self.handle(Some(command), reply)
+    }
+
+    fn handle(&mut self, command: Option<&str>, reply: Reply) -> Result<String, AdventureError> {
+        if let Some(command) = command {
+            self.transcript.push(format!("> {}", command));
+        }
+
+        self.transcript.push(reply.text.clone());
+
+        match reply.status {
+            Status::Waiting => {},
+            Status::Halted => return Err(AdventureError::Halted),
+            Status::Stuck => return Err(AdventureError::Stuck),
+        }
+
+        if let Some(room) = parse_room(&reply.text) {
+            self.rooms.insert(room.name.clone(), room.clone());
+            self.room = Some(room);
+        }
+
+        Ok(reply.text)
+    }
+
+    fn current(&self) -> Result<Room, AdventureError> {
+        self.room.clone().ok_or_else(|| AdventureError::UnexpectedOutput("no room description seen yet".to_owned()))
+    }
+
+    pub fn take(&mut self, item: &str) -> Result<(), AdventureError> {
+        let text = self.command(&format!("take {}", item))?;
+
+        if !text.contains("You take") {
+            return Err(AdventureError::UnexpectedOutput(text));
+        }
+
+        self.inventory.push(item.to_owned());
+        Ok(())
+    }
+
+    pub fn drop_item(&mut self, item: &str) -> Result<(), AdventureError> {
+        let text = self.command(&format!("drop {}", item))?;
+
+        if !text.contains("You drop") {
+            return Err(AdventureError::UnexpectedOutput(text));
+        }
+
+        self.inventory.retain(|held| held != item);
+        Ok(())
+    }
+
+    // Tries the item on a forked machine: it must not end the game, hang it,
+    // or keep us from walking through a door afterwards.
+    pub fn is_safe(&mut self, item: &str) -> Result<bool, AdventureError> {
+        if self.dangerous.contains(item) {
+            return Ok(false);
+        }
+
+        let room = self.current()?;
+        let mut fork = self.console.fork();
+
+        fork.send(&format!("take {}", item));
+        let reply = fork.poll()?;
+
+        if reply.status != Status::Waiting || !reply.text.contains("You take") {
+            return Ok(false);
+        }
+
+        let door = match room.doors.first() {
+            Some(door) => door,
+            None => return Ok(true),
+        };
+
+        fork.send(door);
+        let reply = fork.poll()?;
+
+        if reply.status != Status::Waiting {
+            return Ok(false);
+        }
+
+        Ok(parse_room(&reply.text).is_some_and(|next| next.name != room.name))
+    }
+
+    pub fn explore(&mut self) -> Result<(), AdventureError> {
+        if self.room.is_none() {
+            self.start()?;
+        }
+
+        let room = self.current()?;
+        self.visit(room)
+    }
+
+    fn visit(&mut self, room: Room) -> Result<(), AdventureError> {
+        self.explored.insert(room.name.clone());
+
+        for item in &room.items {
+            if self.inventory.contains(item) {
+                continue;
+            }
+
+            if self.is_safe(item)? {
+                self.take(item)?;
+            } else {
+                self.dangerous.insert(item.clone());
+            }
+        }
+
+        for door in &room.doors {
+            let key = (room.name.clone(), door.clone());
+
+            if self.edges.contains_key(&key) {
+                continue;
+            }
+
+            self.command(door)?;
+            let next = self.current()?;
+
+            if next.name == room.name {
+                // Bounced back, so the door leads to a pressure-sensitive floor
+                self.checkpoint = Some(key);
+                continue;
+            }
+
+            self.edges.insert(key, next.name.clone());
+            self.edges.insert((next.name.clone(), opposite(door).to_owned()), room.name.clone());
+
+            if !self.explored.contains(&next.name) {
+                self.visit(next)?;
+            }
+
+            self.command(opposite(door))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn goto(&mut self, target: &str) -> Result<(), AdventureError> {
+        let start = self.current()?.name;
+        let mut previous = HashMap::new();
+        let mut queue = VecDeque::new();
+
+        previous.insert(start.clone(), None);
+        queue.push_back(start.clone());
+
+        while let Some(room) = queue.pop_front() {
+            if room == target {
+                break;
+            }
+
+            for ((from, door), to) in &self.edges {
+                if *from == room && !previous.contains_key(to) {
+                    previous.insert(to.clone(), Some((from.clone(), door.clone())));
+                    queue.push_back(to.clone());
+                }
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut room = target.to_owned();
+
+        loop {
+            match previous.get(&room) {
+                Some(Some((from, door))) => {
+                    path.push(door.clone());
+                    room = from.clone();
+                },
+                Some(None) => break,
+                None => return Err(AdventureError::UnknownRoom(target.to_owned())),
+            }
+        }
+
+        for door in path.iter().rev() {
+            self.command(door)?;
+        }
+
+        Ok(())
+    }
+
+    // Walks to the checkpoint and tries every subset of the carried items
+    // until the pressure-sensitive floor lets us through.
+    pub fn solve(&mut self) -> Result<String, AdventureError> {
+        let (room, door) = self.checkpoint.clone().ok_or(AdventureError::NoCheckpoint)?;
+        self.goto(&room)?;
+
+        let items = self.inventory.clone();
+
+        for mask in 0..1usize << items.len() {
+            for (index, item) in items.iter().enumerate() {
+                let wanted = mask & (1 << index) != 0;
+                let held = self.inventory.contains(item);
+
+                if wanted && !held {
+                    self.take(item)?;
+                } else if !wanted && held {
+                    self.drop_item(item)?;
+                }
+            }
+
+            match self.command(&door) {
+                Err(AdventureError::Halted) => {
+                    return Ok(self.transcript.last().cloned().unwrap_or_default());
+                },
+                Err(err) => return Err(err),
+                Ok(_) if self.current()?.name != room => {
+                    return Ok(self.transcript.last().cloned().unwrap_or_default());
+                },
+                Ok(_) => {},
+            }
+        }
+
+        Err(AdventureError::NoSolution)
+    }
+
+    // Script lines are `avoid <item>`, `explore`, `goto <room>`, `solve`
+    // or raw game commands. Empty lines and `#` comments are skipped.
+    pub fn run_script(&mut self, script: &str) -> Result<Option<String>, AdventureError> {
+        let mut result = None;
+
+        if self.room.is_none() {
+            self.start()?;
+        }
+
+        for line in script.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (word, rest) = match line.find(' ') {
+                Some(index) => (&line[..index], line[index + 1..].trim()),
+                None => (line, ""),
+            };
+
+            match word {
+                "avoid" => self.avoid(rest),
+                "explore" => self.explore()?,
+                "goto" => self.goto(rest)?,
+                "solve" => result = Some(self.solve()?),
+                _ => { self.command(line)?; },
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    const PRESSURE_FLOOR: &str = "Pressure-Sensitive Floor";
+
+    #[derive(Clone)]
+    struct MockGame {
+        room: &'static str,
+        floor: Vec<(&'static str, &'static str)>,
+        inventory: Vec<&'static str>,
+        output: String,
+        status: Status,
+    }
+
+    impl MockGame {
+        fn new() -> Self {
+            let mut game = Self {
+                room: "Hull Breach",
+                floor: vec![
+                    ("Kitchen", "mug"),
+                    ("Kitchen", "molten lava"),
+                    ("Kitchen", "infinite loop"),
+                    ("Lab", "coil"),
+                    ("Lab", "magnet"),
+                    ("Security Checkpoint", "tape"),
+                ],
+                inventory: vec![],
+                output: String::new(),
+                status: Status::Waiting,
+            };
+            game.describe();
+            game
+        }
+
+        fn doors(room: &str) -> &'static [(&'static str, &'static str)] {
+            match room {
+                "Hull Breach" => &[("north", "Kitchen"), ("east", "Lab")],
+                "Kitchen" => &[("south", "Hull Breach")],
+                "Lab" => &[("west", "Hull Breach"), ("north", "Security Checkpoint")],
+                "Security Checkpoint" => &[("south", "Lab"), ("west", PRESSURE_FLOOR)],
+                _ => &[],
+            }
+        }
+
+        fn weight(item: &str) -> usize {
+            match item {
+                "mug" => 1,
+                "coil" => 2,
+                "tape" => 4,
+                _ => 100,
+            }
+        }
+
+        fn describe(&mut self) {
+            self.output += &format!("\n\n\n== {} ==\nA room.\n\nDoors here lead:\n", self.room);
+
+            for (door, _) in Self::doors(self.room) {
+                self.output += &format!("- {}\n", door);
+            }
+
+            let items = self.floor
+                .iter()
+                .filter(|(room, _)| *room == self.room)
+                .map(|(_, item)| *item)
+                .collect::<Vec<_>>();
+
+            if !items.is_empty() {
+                self.output += "\nItems here:\n";
+
+                for item in items {
+                    self.output += &format!("- {}\n", item);
+                }
+            }
+
+            self.output += "\nCommand?\n";
+        }
+    }
+
+    impl Console for MockGame {
+        fn send(&mut self, line: &str) {
+            if let Some(item) = line.strip_prefix("take ") {
+                let index = self.floor.iter().position(|&(room, known)| room == self.room && known == item).unwrap();
+                let (_, item) = self.floor[index];
+
+                match item {
+                    "molten lava" => {
+                        self.output += "\nYou melt!\n";
+                        self.status = Status::Halted;
+                    },
+                    "infinite loop" => self.status = Status::Stuck,
+                    _ => {
+                        self.floor.remove(index);
+                        self.inventory.push(item);
+                        self.output += &format!("\nYou take the {}.\n\nCommand?\n", item);
+                    },
+                }
+
+                return;
+            }
+
+            if let Some(item) = line.strip_prefix("drop ") {
+                let index = self.inventory.iter().position(|held| *held == item).unwrap();
+                self.floor.push((self.room, self.inventory.remove(index)));
+                self.output += &format!("\nYou drop the {}.\n\nCommand?\n", item);
+                return;
+            }
+
+            if self.inventory.contains(&"magnet") {
+                self.output += "\nThe magnet is stuck to you. You can't move!!\n\nCommand?\n";
+                return;
+            }
+
+            let (_, next) = *Self::doors(self.room).iter().find(|(door, _)| *door == line).unwrap();
+
+            if next == PRESSURE_FLOOR {
+                let weight = self.inventory.iter().map(|item| Self::weight(item)).sum::<usize>();
+                self.output += &format!("\n\n\n== {} ==\nAnalyzing...\n\n", PRESSURE_FLOOR);
+
+                if weight == 5 {
+                    self.output += "Get in by typing 1234 on the keypad.\n";
+                    self.status = Status::Halted;
+                    return;
+                }
+
+                self.output += "A loud voice says \"Alert!\" and you are ejected back to the checkpoint.\n";
+            } else {
+                self.room = next;
+            }
+
+            self.describe();
+        }
+
+        fn poll(&mut self) -> Result<Reply, AdventureError> {
+            Ok(Reply {
+                text: std::mem::take(&mut self.output),
+                status: self.status,
+            })
+        }
+
+        fn fork(&mut self) -> Self {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn intcode_console() {
+        let room = "\n\n== Hull Breach ==\nYou got in through a hole.\n\nDoors here lead:\n- north\n\nCommand?\n";
+        let text = room.bytes().map(|byte| byte.to_string()).collect::<Vec<_>>().join(", ");
+
+        // Prints the room, then halts after reading one line
+        let program = assemble(&format!("
+                arb #text
+            print:
+                jf rel[0], #read
+                out rel[0]
+                arb #1
+                jt #1, #print
+            read:
+                in [char]
+                eq [char], #10, [done]
+                jf [done], #read
+                hlt
+            char: .data 0
+            done: .data 0
+            text: .data {}, 0
+        ", text)).unwrap();
+
+        let mut adventure = Adventure::new(IntcodeConsole::new(VM::new(program)));
+
+        assert_eq!(adventure.start().unwrap(), room);
+        assert_eq!(adventure.room().unwrap().doors, ["north"]);
+        assert!(matches!(adventure.command("north"), Err(AdventureError::Halted)));
+        assert_eq!(adventure.transcript()[1..], ["> north", ""]);
+
+        let mut vm = VM::new(assemble("loop: jt #1, #loop").unwrap());
+        vm.set_instruction_limit(Some(250));
+
+        let mut console = IntcodeConsole::new(vm);
+        console.set_budget(100);
+
+        assert_eq!(console.poll().unwrap().status, Status::Stuck);
+        assert_eq!(console.poll().unwrap().status, Status::Stuck);
+        assert_eq!(console.vm.instructions_executed(), 200);
+
+        // The VM's own limit still applies across polls
+        assert!(matches!(console.poll(), Err(AdventureError::Vm(VmError::LimitExceeded { limit: Limit::Instructions, .. }))));
+        assert_eq!(console.vm.instructions_executed(), 250);
+    }
+
+    #[test]
+    fn parses_rooms() {
+        let text = "\n\n\n== Hull Breach ==\nYou got in through a hole.\n\nDoors here lead:\n- north\n- east\n\nItems here:\n- mug\n\nCommand?\n";
+
+        assert_eq!(parse_room(text), Some(Room {
+            name: "Hull Breach".to_owned(),
+            description: "You got in through a hole.".to_owned(),
+            doors: vec!["north".to_owned(), "east".to_owned()],
+            items: vec!["mug".to_owned()],
+        }));
+    }
+
+    #[test]
+    fn explores_and_avoids_dangerous_items() {
+        let mut adventure = Adventure::new(MockGame::new());
+        adventure.explore().unwrap();
+
+        let mut inventory = adventure.inventory().to_vec();
+        inventory.sort();
+
+        assert_eq!(inventory, ["coil", "mug", "tape"]);
+        assert_eq!(adventure.rooms().len(), 4);
+        assert_eq!(adventure.room().unwrap().name, "Hull Breach");
+        assert_eq!(adventure.checkpoint(), Some(&("Security Checkpoint".to_owned(), "west".to_owned())));
+    }
+
+    #[test]
+    fn script_solves_checkpoint() {
+        let mut adventure = Adventure::new(MockGame::new());
+        let result = adventure.run_script("
+            # the lava is avoided without probing
+            avoid molten lava
+            explore
+            solve
+        ").unwrap();
+
+        assert!(result.unwrap().contains("typing 1234"));
+
+        let mut inventory = adventure.inventory().to_vec();
+        inventory.sort();
+
+        assert_eq!(inventory, ["mug", "tape"]);
+    }
+}
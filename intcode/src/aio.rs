@@ -0,0 +1,300 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::{Future, poll_fn};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll, Wake, Waker};
+
+use futures_core::Stream;
+
+use crate::{VM, RunState, VmError};
+
+pub struct AsyncVM<'a, Input, Context = ()> {
+    vm: VM<'a, Context>,
+    input: Input,
+    error: Option<VmError>,
+    done: bool,
+}
+
+// The VM is never pinned structurally, so moving it is always fine
+impl<'a, Input: Unpin, Context> Unpin for AsyncVM<'a, Input, Context> {}
+
+impl<'a, Input, Context> AsyncVM<'a, Input, Context>
+where
+    Input: Stream<Item = isize> + Unpin,
+{
+    pub fn new(vm: VM<'a, Context>, input: Input) -> Self {
+        Self {
+            vm,
+            input,
+            error: None,
+            done: false,
+        }
+    }
+
+    pub fn vm(&self) -> &VM<'a, Context> {
+        &self.vm
+    }
+
+    pub fn into_vm(self) -> VM<'a, Context> {
+        self.vm
+    }
+
+    pub fn error(&self) -> Option<VmError> {
+        self.error
+    }
+
+    pub async fn next_output(&mut self) -> Option<isize> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<'a, Input, Context> Stream for AsyncVM<'a, Input, Context>
+where
+    Input: Stream<Item = isize> + Unpin,
+{
+    type Item = isize;
+
+    // Errors end the stream and are kept for `error()`
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Option<isize>> {
+        let this = self.get_mut();
+
+        while !this.done {
+            let state = match this.vm.run_until_blocked() {
+                Ok(state) => state,
+                Err(err) => {
+                    this.error = Some(err);
+                    this.done = true;
+                    break;
+                },
+            };
+
+            match state {
+                RunState::Output(value) => return Poll::Ready(Some(value)),
+                RunState::Halted => this.done = true,
+                RunState::NeedsInput => match Pin::new(&mut this.input).poll_next(cx) {
+                    Poll::Ready(Some(value)) => this.vm.queue_input(value),
+                    Poll::Ready(None) => {
+                        this.error = Some(VmError::InputExhausted { ip: this.vm.ip() });
+                        this.done = true;
+                    },
+                    Poll::Pending => return Poll::Pending,
+                },
+            }
+        }
+
+        Poll::Ready(None)
+    }
+}
+
+struct Shared {
+    queue: VecDeque<isize>,
+    waker: Option<Waker>,
+    senders: usize,
+}
+
+pub struct Sender {
+    shared: Rc<RefCell<Shared>>,
+}
+
+pub struct Receiver {
+    shared: Rc<RefCell<Shared>>,
+}
+
+pub fn channel() -> (Sender, Receiver) {
+    let shared = Rc::new(RefCell::new(Shared {
+        queue: VecDeque::new(),
+        waker: None,
+        senders: 1,
+    }));
+
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+impl Sender {
+    pub fn send(&self, value: isize) {
+        let mut shared = self.shared.borrow_mut();
+        shared.queue.push_back(value);
+
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        self.shared.borrow_mut().senders += 1;
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.senders -= 1;
+
+        if shared.senders == 0 {
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Stream for Receiver {
+    type Item = isize;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Option<isize>> {
+        let mut shared = self.shared.borrow_mut();
+
+        match shared.queue.pop_front() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if shared.senders == 0 => Poll::Ready(None),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+type Task<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.ready.lock().unwrap().push_back(self.id);
+    }
+}
+
+#[derive(Default)]
+pub struct LocalExecutor<'a> {
+    tasks: Vec<Option<Task<'a>>>,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl<'a> LocalExecutor<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self, task: impl Future<Output = ()> + 'a) {
+        self.ready.lock().unwrap().push_back(self.tasks.len());
+        self.tasks.push(Some(Box::pin(task)));
+    }
+
+    // Returns once no task can make progress anymore
+    pub fn run(&mut self) {
+        loop {
+            let id = match self.ready.lock().unwrap().pop_front() {
+                Some(id) => id,
+                None => return,
+            };
+
+            let task = match &mut self.tasks[id] {
+                Some(task) => task,
+                None => continue,
+            };
+
+            let waker = Waker::from(Arc::new(TaskWaker { id, ready: self.ready.clone() }));
+
+            if task.as_mut().poll(&mut TaskContext::from_waker(&waker)).is_ready() {
+                self.tasks[id] = None;
+            }
+        }
+    }
+
+    pub fn pending_tasks(&self) -> usize {
+        self.tasks.iter().filter(|task| task.is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn ring_of_machines() {
+        let program = assemble("
+            loop:
+                in [value]
+                add [value], #1, [value]
+                out [value]
+                jt #1, #loop
+            value: .data 0
+        ").unwrap();
+
+        let machines = 200;
+        let rounds = 3;
+        let (first_tx, mut rx) = channel();
+        let mut executor = LocalExecutor::new();
+
+        for _ in 0..machines {
+            let (tx, next_rx) = channel();
+            let mut vm = AsyncVM::new(VM::new(program.clone()), rx);
+
+            executor.spawn(async move {
+                while let Some(value) = vm.next_output().await {
+                    tx.send(value);
+                }
+            });
+
+            rx = next_rx;
+        }
+
+        let result = Rc::new(RefCell::new(None));
+        let result_slot = result.clone();
+
+        executor.spawn(async move {
+            first_tx.send(0);
+
+            for round in 1..=rounds {
+                let value = poll_fn(|cx| Pin::new(&mut rx).poll_next(cx)).await.unwrap();
+
+                if round == rounds {
+                    *result_slot.borrow_mut() = Some(value);
+                    return;
+                }
+
+                first_tx.send(value);
+            }
+        });
+
+        executor.run();
+
+        assert_eq!(*result.borrow(), Some(machines * rounds));
+    }
+
+    #[test]
+    fn closed_input_ends_stream_with_error() {
+        let (tx, rx) = channel();
+        let mut vm = AsyncVM::new(VM::new(vec![3, 9, 4, 9, 3, 9, 4, 9, 99, 0]), rx);
+        let outputs = Rc::new(RefCell::new(Vec::new()));
+        let collected = outputs.clone();
+
+        tx.send(7);
+        drop(tx);
+
+        let mut executor = LocalExecutor::new();
+
+        executor.spawn(async move {
+            while let Some(value) = vm.next_output().await {
+                collected.borrow_mut().push(value);
+            }
+
+            assert_eq!(vm.error(), Some(VmError::InputExhausted { ip: 4 }));
+        });
+
+        executor.run();
+
+        assert_eq!(*outputs.borrow(), [7]);
+        assert_eq!(executor.pending_tasks(), 0);
+    }
+}
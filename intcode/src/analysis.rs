@@ -0,0 +1,440 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::{Op, Mode};
+use crate::disasm::{Instruction, label};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    Call,
+    // From a call site to the instruction the callee returns to
+    CallReturn,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Terminator {
+    Fallthrough,
+    Jump,
+    Halt,
+    Return,
+    // A jump to a computed address that isn't a recognized return
+    Indirect,
+    // The next cell doesn't decode to an instruction
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+impl Block {
+    pub fn end(&self) -> usize {
+        self.instructions.last().map_or(self.start, Instruction::next_addr)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SelfModification {
+    pub addr: usize,
+    pub target: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Subroutine {
+    pub entry: usize,
+    pub callers: Vec<usize>,
+    pub returns: Vec<usize>,
+    pub blocks: BTreeSet<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub blocks: BTreeMap<usize, Block>,
+    pub edges: Vec<Edge>,
+    pub subroutines: BTreeMap<usize, Subroutine>,
+    pub self_modifications: Vec<SelfModification>,
+    pub invalid: BTreeSet<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Flow {
+    Next,
+    Branch(usize),
+    Jump(usize),
+    Call(usize),
+    Return,
+    Indirect { conditional: bool },
+    Halt,
+}
+
+pub fn analyze(mem: &[isize]) -> Analysis {
+    analyze_from(mem, &[0])
+}
+
+pub fn analyze_from(mem: &[isize], entries: &[usize]) -> Analysis {
+    let mut instructions = BTreeMap::new();
+    let mut flows = BTreeMap::new();
+    let mut invalid = BTreeSet::new();
+    let mut leaders = entries.iter().copied().collect::<BTreeSet<_>>();
+
+    // Every work item carries the constants pushed through the relative base
+    // since the last leader, so calls can be told apart from plain jumps.
+    let mut work = entries.iter().map(|&addr| (addr, Vec::new())).collect::<Vec<_>>();
+
+    while let Some((addr, mut pushed)) = work.pop() {
+        if instructions.contains_key(&addr) || invalid.contains(&addr) {
+            continue;
+        }
+
+        let instruction = match Instruction::decode(mem, addr) {
+            Some(instruction) => instruction,
+            None => {
+                invalid.insert(addr);
+                continue;
+            },
+        };
+
+        let next = instruction.next_addr();
+        let flow = flow(&instruction, &pushed);
+
+        match flow {
+            Flow::Next => {
+                pushed.extend(pushed_constant(&instruction));
+                work.push((next, pushed));
+            },
+            Flow::Branch(target) => {
+                leaders.extend(&[target, next]);
+                work.push((target, Vec::new()));
+                work.push((next, Vec::new()));
+            },
+            Flow::Jump(target) => {
+                leaders.insert(target);
+                work.push((target, Vec::new()));
+            },
+            Flow::Call(target) => {
+                leaders.extend(&[target, next]);
+                work.push((target, Vec::new()));
+                work.push((next, Vec::new()));
+            },
+            Flow::Indirect { conditional: true } => {
+                leaders.insert(next);
+                work.push((next, Vec::new()));
+            },
+            Flow::Return | Flow::Indirect { conditional: false } | Flow::Halt => {},
+        }
+
+        flows.insert(addr, flow);
+        instructions.insert(addr, instruction);
+    }
+
+    let mut blocks = BTreeMap::new();
+    let mut edges = Vec::new();
+
+    for &start in leaders.iter().filter(|addr| instructions.contains_key(addr)) {
+        let mut block = Block {
+            start,
+            instructions: Vec::new(),
+            terminator: Terminator::Fallthrough,
+        };
+        let mut addr = start;
+
+        loop {
+            let instruction = instructions[&addr].clone();
+            let next = instruction.next_addr();
+            block.instructions.push(instruction);
+
+            let mut edge = |to, kind| edges.push(Edge { from: start, to, kind });
+
+            match flows[&addr] {
+                Flow::Next if invalid.contains(&next) => block.terminator = Terminator::Invalid,
+                Flow::Next if leaders.contains(&next) => edge(next, EdgeKind::Fallthrough),
+                Flow::Next => {
+                    addr = next;
+                    continue;
+                },
+                Flow::Branch(target) => {
+                    block.terminator = Terminator::Jump;
+                    edge(target, EdgeKind::Jump);
+                    edge(next, EdgeKind::Fallthrough);
+                },
+                Flow::Jump(target) => {
+                    block.terminator = Terminator::Jump;
+                    edge(target, EdgeKind::Jump);
+                },
+                Flow::Call(target) => {
+                    block.terminator = Terminator::Jump;
+                    edge(target, EdgeKind::Call);
+                    edge(next, EdgeKind::CallReturn);
+                },
+                Flow::Indirect { conditional } => {
+                    block.terminator = Terminator::Indirect;
+
+                    if conditional {
+                        edge(next, EdgeKind::Fallthrough);
+                    }
+                },
+                Flow::Return => block.terminator = Terminator::Return,
+                Flow::Halt => block.terminator = Terminator::Halt,
+            }
+
+            break;
+        }
+
+        blocks.insert(start, block);
+    }
+
+    // Only edges into decoded blocks are kept, jumps into data end up in `invalid`
+    edges.retain(|edge| blocks.contains_key(&edge.to));
+
+    let subroutines = find_subroutines(&blocks, &edges);
+    let self_modifications = find_self_modifications(&instructions);
+
+    Analysis {
+        blocks,
+        edges,
+        subroutines,
+        self_modifications,
+        invalid,
+    }
+}
+
+fn flow(instruction: &Instruction, pushed: &[isize]) -> Flow {
+    let op = instruction.op();
+    let next = instruction.next_addr();
+
+    match op {
+        Op::Halt => return Flow::Halt,
+        Op::JumpIfTrue | Op::JumpIfFalse => {},
+        _ => return Flow::Next,
+    }
+
+    let always = match (instruction.op_code.mode(0), instruction.args[0]) {
+        (Mode::Immediate, cond) => Some((cond != 0) == (op == Op::JumpIfTrue)),
+        _ => None,
+    };
+
+    match (always, instruction.jump_target(), instruction.op_code.mode(1)) {
+        (Some(false), _, _) => Flow::Next,
+        (Some(true), Some(target), _) if pushed.contains(&(next as isize)) => Flow::Call(target),
+        (Some(true), Some(target), _) => Flow::Jump(target),
+        (None, Some(target), _) => Flow::Branch(target),
+        (Some(true), None, Mode::Relative) => Flow::Return,
+        (always, None, _) => Flow::Indirect { conditional: always.is_none() },
+    }
+}
+
+// A constant stored relative to the base, e.g. `add #ret, #0, rel[+0]`
+fn pushed_constant(instruction: &Instruction) -> Option<isize> {
+    let modes = (0..3).map(|index| instruction.op_code.mode(index)).collect::<Vec<_>>();
+
+    if modes[..] != [Mode::Immediate, Mode::Immediate, Mode::Relative] {
+        return None;
+    }
+
+    match instruction.op() {
+        Op::Add => instruction.args[0].checked_add(instruction.args[1]),
+        Op::Mul => instruction.args[0].checked_mul(instruction.args[1]),
+        _ => None,
+    }
+}
+
+fn find_subroutines(blocks: &BTreeMap<usize, Block>, edges: &[Edge]) -> BTreeMap<usize, Subroutine> {
+    let mut subroutines = BTreeMap::<usize, Subroutine>::new();
+
+    for edge in edges.iter().filter(|edge| edge.kind == EdgeKind::Call) {
+        let caller = blocks[&edge.from].instructions.last().expect("call instruction").addr;
+        let subroutine = subroutines.entry(edge.to).or_default();
+
+        subroutine.entry = edge.to;
+        subroutine.callers.push(caller);
+    }
+
+    for subroutine in subroutines.values_mut() {
+        let mut work = vec![subroutine.entry];
+
+        // Calls are stepped over through their return edge
+        while let Some(start) = work.pop() {
+            if !subroutine.blocks.insert(start) {
+                continue;
+            }
+
+            if blocks[&start].terminator == Terminator::Return {
+                subroutine.returns.push(blocks[&start].instructions.last().expect("return instruction").addr);
+            }
+
+            work.extend(
+                edges
+                    .iter()
+                    .filter(|edge| edge.from == start && edge.kind != EdgeKind::Call)
+                    .map(|edge| edge.to),
+            );
+        }
+
+        subroutine.returns.sort_unstable();
+    }
+
+    subroutines
+}
+
+fn find_self_modifications(instructions: &BTreeMap<usize, Instruction>) -> Vec<SelfModification> {
+    let covers = |target: usize| {
+        instructions
+            .range(..=target)
+            .next_back()
+            .is_some_and(|(_, instruction)| target < instruction.next_addr())
+    };
+
+    instructions
+        .values()
+        .filter(|instruction| instruction.op().writes())
+        .filter_map(|instruction| {
+            let index = instruction.args.len() - 1;
+
+            match (instruction.op_code.mode(index), instruction.args[index]) {
+                (Mode::Position, target) if target >= 0 && covers(target as usize) => {
+                    Some(SelfModification { addr: instruction.addr, target: target as usize })
+                },
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+impl Analysis {
+    pub fn block_containing(&self, addr: usize) -> Option<&Block> {
+        self.blocks
+            .range(..=addr)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| addr < block.end())
+    }
+
+    pub fn to_dot(&self) -> String {
+        let modified = self.self_modifications.iter().map(|modification| modification.addr).collect::<BTreeSet<_>>();
+        let mut dot = String::new();
+
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for block in self.blocks.values() {
+            let mut text = format!("{}:\\l", label(block.start));
+
+            for instruction in &block.instructions {
+                text += &format!("{:>6}  {}\\l", instruction.addr, escape(&instruction.to_string()));
+            }
+
+            let mut attrs = format!("label=\"{}\"", text);
+
+            if block.instructions.iter().any(|instruction| modified.contains(&instruction.addr)) {
+                attrs += ", color=red";
+            }
+
+            if self.subroutines.contains_key(&block.start) {
+                attrs += ", peripheries=2";
+            }
+
+            writeln!(dot, "    b{} [{}];", block.start, attrs).unwrap();
+        }
+
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Fallthrough | EdgeKind::Jump => "",
+                EdgeKind::Call => " [style=dashed, label=\"call\"]",
+                EdgeKind::CallReturn => " [style=dotted]",
+            };
+
+            writeln!(dot, "    b{} -> b{}{};", edge.from, edge.to, style).unwrap();
+        }
+
+        writeln!(dot, "}}").unwrap();
+
+        dot
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    const PROGRAM: &str = "
+            arb #100
+            add #back, #0, rel[+0]
+            jt #1, #twice
+        back:
+            eq [x], #6, [t]
+            jt [t], #done
+            add #0, #0, [patch]
+            hlt
+        done:
+            out [x]
+        patch:
+            hlt
+        twice:
+            mul [x], #2, [x]
+            jf #0, rel[+0]
+        x: .data 3
+        t: .data 0
+    ";
+
+    fn analysis() -> Analysis {
+        analyze(&assemble(PROGRAM).unwrap())
+    }
+
+    #[test]
+    fn blocks_and_edges() {
+        let analysis = analysis();
+
+        assert_eq!(analysis.blocks.keys().copied().collect::<Vec<_>>(), [0, 9, 16, 21, 24]);
+        assert_eq!(analysis.blocks[&9].terminator, Terminator::Jump);
+        assert_eq!(analysis.blocks[&24].terminator, Terminator::Return);
+        assert_eq!(analysis.block_containing(13).unwrap().start, 9);
+
+        let edges = analysis.edges.iter().map(|edge| (edge.from, edge.to, edge.kind)).collect::<Vec<_>>();
+
+        assert_eq!(edges, [
+            (0, 24, EdgeKind::Call),
+            (0, 9, EdgeKind::CallReturn),
+            (9, 21, EdgeKind::Jump),
+            (9, 16, EdgeKind::Fallthrough),
+        ]);
+    }
+
+    #[test]
+    fn subroutines_and_self_modification() {
+        let analysis = analysis();
+        let twice = &analysis.subroutines[&24];
+
+        assert_eq!(analysis.subroutines.len(), 1);
+        assert_eq!(twice.callers, [6]);
+        assert_eq!(twice.returns, [28]);
+        assert_eq!(analysis.self_modifications, [SelfModification { addr: 16, target: 23 }]);
+        assert!(analysis.invalid.is_empty());
+    }
+
+    #[test]
+    fn dot_export() {
+        let dot = analysis().to_dot();
+
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("b0 -> b24 [style=dashed, label=\"call\"];"));
+        assert!(dot.contains("b16 [label=\"L16:\\l    16  add #0, #0, [23]\\l    20  hlt\\l\", color=red];"));
+        assert!(dot.contains("peripheries=2"));
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+
+use crate::{OpCode, Op, Mode, RunState, VmError};
+use crate::analysis::analyze;
+use crate::disasm::Instruction;
+
+// Why compiled code handed control back to `Machine::run`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Exit {
+    Halted,
+    NeedsInput,
+    // The ip is not the start of a valid compiled instruction
+    Interpret,
+}
+
+pub type Compiled = fn(&mut Machine) -> Result<Exit, VmError>;
+
+// Runtime state shared by generated code and the fallback interpreter.
+// Compiled instructions are invalidated as soon as one of their cells is
+// written, from then on they run interpreted.
+pub struct Machine {
+    mem: Vec<isize>,
+    ip: usize,
+    relative_base: isize,
+    inputs: VecDeque<isize>,
+    outputs: Vec<isize>,
+    // Whether a valid compiled instruction starts at an address
+    compiled: Vec<bool>,
+    // Start of the compiled instruction covering a cell
+    covers: Vec<Option<usize>>,
+}
+
+impl Machine {
+    // `code` lists the (start, size) of every compiled instruction, as
+    // emitted in the generated `CODE` table
+    pub fn new(mem: impl Into<Vec<isize>>, code: &[(usize, usize)]) -> Self {
+        let mem = mem.into();
+        let mut compiled = vec![false; mem.len()];
+        let mut covers = vec![None; mem.len()];
+
+        for &(start, size) in code {
+            if start + size > mem.len() {
+                continue;
+            }
+
+            compiled[start] = true;
+
+            for cover in &mut covers[start..start + size] {
+                *cover = Some(start);
+            }
+        }
+
+        Self {
+            mem,
+            ip: 0,
+            relative_base: 0,
+            inputs: VecDeque::new(),
+            outputs: Vec::new(),
+            compiled,
+            covers,
+        }
+    }
+
+    pub fn add_input(&mut self, value: isize) {
+        self.inputs.push_back(value);
+    }
+
+    pub fn outputs(&self) -> &[isize] {
+        &self.outputs
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    pub fn mem(&self) -> &[isize] {
+        &self.mem
+    }
+
+    pub fn is_compiled(&self, addr: usize) -> bool {
+        self.compiled.get(addr).copied().unwrap_or(false)
+    }
+
+    pub fn read(&self, addr: usize) -> isize {
+        self.mem.get(addr).copied().unwrap_or(0)
+    }
+
+    pub fn write(&mut self, addr: usize, value: isize) {
+        if addr >= self.mem.len() {
+            self.mem.resize(addr + 1, 0);
+        }
+
+        self.mem[addr] = value;
+
+        if let Some(&Some(start)) = self.covers.get(addr) {
+            self.compiled[start] = false;
+        }
+    }
+
+    pub fn set_ip(&mut self, ip: usize) {
+        self.ip = ip;
+    }
+
+    pub fn adjust_relative_base(&mut self, ip: usize, offset: isize) -> Result<(), VmError> {
+        self.relative_base = self.relative_base.checked_add(offset).ok_or(VmError::Overflow { ip })?;
+
+        Ok(())
+    }
+
+    pub fn pop_input(&mut self) -> Option<isize> {
+        self.inputs.pop_front()
+    }
+
+    pub fn push_output(&mut self, value: isize) {
+        self.outputs.push(value);
+    }
+
+    pub fn addr(&self, ip: usize, addr: isize) -> Result<usize, VmError> {
+        if addr < 0 {
+            return Err(VmError::NegativeAddress { ip, addr });
+        }
+
+        Ok(addr as usize)
+    }
+
+    // Address of a relative operand
+    pub fn relative(&self, ip: usize, offset: isize) -> Result<usize, VmError> {
+        let addr = self.relative_base.checked_add(offset).ok_or(VmError::Overflow { ip })?;
+
+        self.addr(ip, addr)
+    }
+
+    // Runs compiled code wherever it is still valid and interprets the rest,
+    // until the program halts or needs input
+    pub fn run(&mut self, compiled: Compiled) -> Result<RunState, VmError> {
+        loop {
+            let exit = if self.is_compiled(self.ip) {
+                compiled(self)?
+            } else {
+                self.interpret()?
+            };
+
+            match exit {
+                Exit::Halted => return Ok(RunState::Halted),
+                Exit::NeedsInput => return Ok(RunState::NeedsInput),
+                Exit::Interpret => {},
+            }
+        }
+    }
+
+    // Interprets instructions until the ip reaches compiled code again
+    fn interpret(&mut self) -> Result<Exit, VmError> {
+        while !self.is_compiled(self.ip) {
+            let op_code = OpCode::parse(self.read(self.ip)).map_err(|err| err.at(self.ip))?;
+
+            match op_code.op() {
+                Op::Add | Op::Mul | Op::LessThan | Op::Equals => {
+                    let a = self.load(&op_code, 0)?;
+                    let b = self.load(&op_code, 1)?;
+                    let value = match op_code.op() {
+                        Op::Add => a.checked_add(b).ok_or(VmError::Overflow { ip: self.ip })?,
+                        Op::Mul => a.checked_mul(b).ok_or(VmError::Overflow { ip: self.ip })?,
+                        Op::LessThan => (a < b) as isize,
+                        _ => (a == b) as isize,
+                    };
+                    self.store(&op_code, 2, value)?;
+                    self.ip += 4;
+                },
+                Op::ReadInput => {
+                    let value = match self.inputs.pop_front() {
+                        Some(value) => value,
+                        None => return Ok(Exit::NeedsInput),
+                    };
+
+                    self.store(&op_code, 0, value)?;
+                    self.ip += 2;
+                },
+                Op::WriteOutput => {
+                    let value = self.load(&op_code, 0)?;
+                    self.outputs.push(value);
+                    self.ip += 2;
+                },
+                Op::JumpIfTrue | Op::JumpIfFalse => {
+                    let cond = self.load(&op_code, 0)?;
+                    let target = self.load(&op_code, 1)?;
+
+                    if (cond != 0) == (op_code.op() == Op::JumpIfTrue) {
+                        self.ip = self.addr(self.ip, target)?;
+                    } else {
+                        self.ip += 3;
+                    }
+                },
+                Op::AdjustRelativeBase => {
+                    let offset = self.load(&op_code, 0)?;
+                    self.adjust_relative_base(self.ip, offset)?;
+                    self.ip += 2;
+                },
+                Op::Halt => return Ok(Exit::Halted),
+            }
+        }
+
+        Ok(Exit::Interpret)
+    }
+
+    fn load(&self, op_code: &OpCode, index: usize) -> Result<isize, VmError> {
+        let arg = self.read(self.ip + 1 + index);
+
+        let addr = match op_code.mode(index) {
+            Mode::Immediate => return Ok(arg),
+            Mode::Position => self.addr(self.ip, arg)?,
+            Mode::Relative => self.relative(self.ip, arg)?,
+        };
+
+        Ok(self.read(addr))
+    }
+
+    fn store(&mut self, op_code: &OpCode, index: usize, value: isize) -> Result<(), VmError> {
+        let arg = self.read(self.ip + 1 + index);
+
+        let addr = match op_code.mode(index) {
+            Mode::Immediate => return Err(VmError::WriteToImmediate { ip: self.ip }),
+            Mode::Position => self.addr(self.ip, arg)?,
+            Mode::Relative => self.relative(self.ip, arg)?,
+        };
+
+        self.write(addr, value);
+
+        Ok(())
+    }
+}
+
+// Reachable instructions that no instruction statically writes to, don't
+// overlap other instructions and don't use negative addresses. Everything
+// else is left to the interpreter.
+pub fn stable_code(mem: &[isize]) -> Vec<Instruction> {
+    let analysis = analyze(mem);
+    let mut instructions = BTreeMap::new();
+
+    for block in analysis.blocks.values() {
+        for instruction in &block.instructions {
+            instructions.insert(instruction.addr, instruction.clone());
+        }
+    }
+
+    let mut covered = vec![0; mem.len()];
+
+    for instruction in instructions.values() {
+        for count in &mut covered[instruction.addr..instruction.next_addr()] {
+            *count += 1;
+        }
+    }
+
+    for modification in &analysis.self_modifications {
+        if let Some(count) = covered.get_mut(modification.target) {
+            *count += 1;
+        }
+    }
+
+    instructions
+        .into_values()
+        .filter(|instruction| covered[instruction.addr..instruction.next_addr()].iter().all(|&count| count == 1))
+        .filter(|instruction| {
+            instruction.args.iter().enumerate().all(|(index, &arg)| {
+                instruction.op_code.mode(index) != Mode::Position || arg >= 0
+            })
+        })
+        .collect()
+}
+
+// Emits a Rust module with a `CODE` table and a `run` function to pass to
+// `Machine::run`
+pub fn compile(mem: &[isize]) -> String {
+    let instructions = stable_code(mem);
+    let mut out = String::new();
+
+    writeln!(out, "// Generated by intcode::aot from a {} cell program, do not edit.", mem.len()).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "use intcode::VmError;").unwrap();
+    writeln!(out, "use intcode::aot::{{Machine, Exit}};").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "pub const CODE: &[(usize, usize)] = &[").unwrap();
+
+    for instruction in &instructions {
+        writeln!(out, "    ({}, {}),", instruction.addr, instruction.size()).unwrap();
+    }
+
+    writeln!(out, "];").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "pub fn run(m: &mut Machine) -> Result<Exit, VmError> {{").unwrap();
+    writeln!(out, "    loop {{").unwrap();
+    writeln!(out, "        let ip = m.ip();").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "        if !m.is_compiled(ip) {{").unwrap();
+    writeln!(out, "            return Ok(Exit::Interpret);").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "        match ip {{").unwrap();
+
+    for instruction in &instructions {
+        writeln!(out, "            // {}", instruction).unwrap();
+        writeln!(out, "            {} => {{", instruction.addr).unwrap();
+
+        for line in body(instruction) {
+            writeln!(out, "                {}", line).unwrap();
+        }
+
+        writeln!(out, "            }},").unwrap();
+    }
+
+    writeln!(out, "            _ => return Ok(Exit::Interpret),").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+
+    out
+}
+
+fn body(instruction: &Instruction) -> Vec<String> {
+    let ip = instruction.addr;
+    let next = instruction.next_addr();
+    let load = |index| load(instruction, index);
+    let store = |index| store(instruction, index);
+
+    match instruction.op() {
+        Op::Add | Op::Mul => {
+            let method = if instruction.op() == Op::Add { "checked_add" } else { "checked_mul" };
+
+            vec![
+                format!("let a: isize = {};", load(0)),
+                format!("let b: isize = {};", load(1)),
+                format!("let value = a.{}(b).ok_or(VmError::Overflow {{ ip: {} }})?;", method, ip),
+                format!("m.write({}, value);", store(2)),
+                format!("m.set_ip({});", next),
+            ]
+        },
+        Op::LessThan | Op::Equals => {
+            let operator = if instruction.op() == Op::LessThan { "<" } else { "==" };
+
+            vec![
+                format!("let a = {};", load(0)),
+                format!("let b = {};", load(1)),
+                format!("m.write({}, (a {} b) as isize);", store(2), operator),
+                format!("m.set_ip({});", next),
+            ]
+        },
+        Op::ReadInput => vec![
+            "let value = match m.pop_input() {".to_owned(),
+            "    Some(value) => value,".to_owned(),
+            "    None => return Ok(Exit::NeedsInput),".to_owned(),
+            "};".to_owned(),
+            format!("m.write({}, value);", store(0)),
+            format!("m.set_ip({});", next),
+        ],
+        Op::WriteOutput => vec![
+            format!("let value = {};", load(0)),
+            "m.push_output(value);".to_owned(),
+            format!("m.set_ip({});", next),
+        ],
+        Op::JumpIfTrue | Op::JumpIfFalse => {
+            let taken = if instruction.op() == Op::JumpIfTrue { "!=" } else { "==" };
+            let jump = match instruction.jump_target() {
+                Some(target) => vec![format!("m.set_ip({});", target)],
+                None => vec![
+                    format!("let target = {};", load(1)),
+                    format!("m.set_ip(m.addr({}, target)?);", ip),
+                ],
+            };
+
+            // Constant conditions are resolved here
+            if instruction.op_code.mode(0) == Mode::Immediate {
+                let cond = instruction.args[0];
+
+                if (cond != 0) == (instruction.op() == Op::JumpIfTrue) {
+                    return jump;
+                }
+
+                return vec![format!("m.set_ip({});", next)];
+            }
+
+            let mut lines = vec![format!("if {} {} 0 {{", load(0), taken)];
+            lines.extend(jump.into_iter().map(|line| format!("    {}", line)));
+            lines.push("} else {".to_owned());
+            lines.push(format!("    m.set_ip({});", next));
+            lines.push("}".to_owned());
+            lines
+        },
+        Op::AdjustRelativeBase => vec![
+            format!("let offset = {};", load(0)),
+            format!("m.adjust_relative_base({}, offset)?;", ip),
+            format!("m.set_ip({});", next),
+        ],
+        Op::Halt => vec!["return Ok(Exit::Halted);".to_owned()],
+    }
+}
+
+fn load(instruction: &Instruction, index: usize) -> String {
+    let arg = instruction.args[index];
+
+    match instruction.op_code.mode(index) {
+        Mode::Immediate => arg.to_string(),
+        Mode::Position => format!("m.read({})", arg),
+        Mode::Relative => format!("m.read({})", relative(instruction, index)),
+    }
+}
+
+fn store(instruction: &Instruction, index: usize) -> String {
+    match instruction.op_code.mode(index) {
+        Mode::Relative => relative(instruction, index),
+        _ => instruction.args[index].to_string(),
+    }
+}
+
+fn relative(instruction: &Instruction, index: usize) -> String {
+    format!("m.relative({}, {})?", instruction.addr, instruction.args[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn skips_modified_code() {
+        let mem = assemble("
+                add #5, #0, [patch]
+            patch: out #1
+                hlt
+        ").unwrap();
+
+        let addrs = stable_code(&mem).iter().map(|instruction| instruction.addr).collect::<Vec<_>>();
+        assert_eq!(addrs, [0, 6]);
+    }
+
+    #[test]
+    fn emits_state_machine() {
+        let source = compile(&assemble("in rel[-1]\njt [0], #0\nhlt").unwrap());
+
+        assert!(source.contains("pub const CODE: &[(usize, usize)] = &[\n    (0, 2),\n    (2, 3),\n    (5, 1),\n];"));
+        assert!(source.contains("m.write(m.relative(0, -1)?, value);"));
+        assert!(source.contains("            2 => {\n                if m.read(0) != 0 {\n                    m.set_ip(0);"));
+    }
+
+    #[test]
+    fn invalidates_written_code() {
+        let mut machine = Machine::new(vec![1, 0, 0, 0, 99], &[(0, 4), (4, 1)]);
+        assert!(machine.is_compiled(4));
+
+        machine.write(4, 104);
+        assert!(!machine.is_compiled(4));
+        assert!(machine.is_compiled(0));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{VM, RunState, VmError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsciiRun {
+    pub lines: Vec<String>,
+    // Outputs outside of the ASCII range, usually the puzzle answer
+    pub values: Vec<isize>,
+    pub state: RunState,
+}
+
+pub struct AsciiVM<'a> {
+    vm: VM<'a>,
+    line: String,
+}
+
+impl<'a> AsciiVM<'a> {
+    pub fn new(vm: VM<'a>) -> Self {
+        Self {
+            vm,
+            line: String::new(),
+        }
+    }
+
+    pub fn vm(&self) -> &VM<'a> {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VM<'a> {
+        &mut self.vm
+    }
+
+    pub fn send_line(&mut self, line: &str) {
+        for byte in line.bytes() {
+            self.vm.queue_input(byte as isize);
+        }
+
+        self.vm.queue_input('\n' as isize);
+    }
+
+    // Runs until the program halts or waits for input.
+    // A trailing prompt without newline is returned as the last line.
+    pub fn run(&mut self) -> Result<AsciiRun, VmError> {
+        let mut lines = Vec::new();
+        let mut values = Vec::new();
+
+        let state = loop {
+            match self.vm.run_until_blocked()? {
+                RunState::Output(value) if value == '\n' as isize => {
+                    lines.push(std::mem::take(&mut self.line));
+                },
+                RunState::Output(value) if (0..=127).contains(&value) => {
+                    self.line.push(value as u8 as char);
+                },
+                RunState::Output(value) => values.push(value),
+                state => break state,
+            }
+        };
+
+        if !self.line.is_empty() {
+            lines.push(std::mem::take(&mut self.line));
+        }
+
+        Ok(AsciiRun { lines, values, state })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn echo() {
+        let program = assemble("
+                out #72
+                out #105
+                out #10
+                out #62
+            loop:
+                in [c]
+                out [c]
+                eq [c], #10, [t]
+                jf [t], #loop
+                out #1000
+                hlt
+            c: .data 0
+            t: .data 0
+        ").unwrap();
+
+        let mut vm = AsciiVM::new(VM::new(program));
+
+        assert_eq!(vm.run(), Ok(AsciiRun {
+            lines: vec!["Hi".to_owned(), ">".to_owned()],
+            values: vec![],
+            state: RunState::NeedsInput,
+        }));
+
+        vm.send_line("ok");
+
+        assert_eq!(vm.run(), Ok(AsciiRun {
+            lines: vec!["ok".to_owned()],
+            values: vec![1000],
+            state: RunState::Halted,
+        }));
+    }
+}
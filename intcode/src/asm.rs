@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use crate::{Op, Mode, AsmError};
+
+enum Value {
+    Number(isize),
+    Label(String),
+}
+
+struct Operand {
+    mode: Mode,
+    value: Value,
+}
+
+enum Statement {
+    Instruction { op: Op, operands: Vec<Operand> },
+    Data(Vec<Value>),
+    Zero(usize),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction { operands, .. } => 1 + operands.len(),
+            Statement::Data(values) => values.len(),
+            Statement::Zero(count) => *count,
+        }
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<isize>, AsmError> {
+    let mut statements = Vec::new();
+    let mut labels = HashMap::new();
+    let mut addr = 0;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let mut line = strip_comment(line).trim();
+
+        while let Some((name, rest)) = split_label(line) {
+            if labels.insert(name.to_owned(), addr).is_some() {
+                return Err(AsmError::new(line_number, format!("duplicate label `{}`", name)));
+            }
+
+            line = rest.trim();
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(line).map_err(|message| AsmError::new(line_number, message))?;
+        addr += statement.size();
+        statements.push((line_number, statement));
+    }
+
+    let mut mem = Vec::with_capacity(addr);
+
+    for (line_number, statement) in statements {
+        let resolve = |value: &Value| match value {
+            Value::Number(number) => Ok(*number),
+            Value::Label(name) => labels
+                .get(name)
+                .map(|&addr| addr as isize)
+                .ok_or_else(|| AsmError::new(line_number, format!("undefined label `{}`", name))),
+        };
+
+        match statement {
+            Statement::Instruction { op, operands } => {
+                let mut code = op.code();
+                let mut factor = 100;
+
+                for operand in &operands {
+                    code += operand.mode.code() * factor;
+                    factor *= 10;
+                }
+
+                mem.push(code);
+
+                for operand in &operands {
+                    mem.push(resolve(&operand.value)?);
+                }
+            },
+            Statement::Data(values) => {
+                for value in &values {
+                    mem.push(resolve(value)?);
+                }
+            },
+            Statement::Zero(count) => mem.resize(mem.len() + count, 0),
+        }
+    }
+
+    Ok(mem)
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(';') {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let index = line.find(':')?;
+    let name = line[..index].trim();
+
+    if !is_identifier(name) {
+        return None;
+    }
+
+    Some((name, &line[index + 1..]))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {},
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_statement(line: &str) -> Result<Statement, String> {
+    let (word, rest) = match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    };
+
+    let args = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(str::trim).collect::<Vec<_>>()
+    };
+
+    match word {
+        ".data" => {
+            if args.is_empty() {
+                return Err(".data needs at least one value".to_owned());
+            }
+
+            let values = args
+                .into_iter()
+                .map(parse_value)
+                .collect::<Result<_, _>>()?;
+
+            Ok(Statement::Data(values))
+        },
+        ".zero" => match args.as_slice() {
+            [count] => count
+                .parse()
+                .map(Statement::Zero)
+                .map_err(|_| format!("invalid .zero count `{}`", count)),
+            _ => Err(".zero takes exactly one count".to_owned()),
+        },
+        _ => {
+            let op = Op::from_mnemonic(word).ok_or_else(|| format!("unknown mnemonic `{}`", word))?;
+
+            if args.len() != op.arg_count() {
+                return Err(format!("`{}` takes {} operands, found {}", word, op.arg_count(), args.len()));
+            }
+
+            let operands = args
+                .into_iter()
+                .map(parse_operand)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if op.writes() && operands.last().map(|operand| operand.mode) == Some(Mode::Immediate) {
+                return Err(format!("`{}` cannot write to an immediate operand", word));
+            }
+
+            Ok(Statement::Instruction { op, operands })
+        },
+    }
+}
+
+fn parse_operand(arg: &str) -> Result<Operand, String> {
+    let (mode, value) = if let Some(value) = arg.strip_prefix('#') {
+        (Mode::Immediate, value)
+    } else if let Some(value) = arg.strip_prefix("rel[").and_then(|arg| arg.strip_suffix(']')) {
+        (Mode::Relative, value)
+    } else if let Some(value) = arg.strip_prefix('[').and_then(|arg| arg.strip_suffix(']')) {
+        (Mode::Position, value)
+    } else {
+        return Err(format!("invalid operand `{}`, expected `[addr]`, `#value` or `rel[offset]`", arg));
+    };
+
+    Ok(Operand {
+        mode,
+        value: parse_value(value.trim())?,
+    })
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if is_identifier(value) {
+        return Ok(Value::Label(value.to_owned()));
+    }
+
+    value
+        .trim_start_matches('+')
+        .parse()
+        .map(Value::Number)
+        .map_err(|_| format!("invalid value `{}`", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VM;
+
+    #[test]
+    fn encodes_modes() {
+        let mem = assemble("add #100, [5], rel[-2]\nhlt").unwrap();
+        assert_eq!(mem, [20101, 100, 5, -2, 99]);
+    }
+
+    #[test]
+    fn countdown() {
+        let source = "
+            ; print 3, 2, 1
+            loop:
+                out [counter]
+                add [counter], #-1, [counter]
+                jt [counter], #loop
+                hlt
+            counter: .data 3
+        ";
+
+        let mut vm = VM::new(assemble(source).unwrap());
+        vm.run().unwrap();
+
+        assert_eq!(vm.outputs(), [3, 2, 1]);
+    }
+
+    #[test]
+    fn zero_directive() {
+        let mem = assemble("hlt\n.zero 3\nend: .data end").unwrap();
+        assert_eq!(mem, [99, 0, 0, 0, 4]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| assemble(source).unwrap_err();
+
+        assert_eq!(error("hlt\nfoo [1]").line, 2);
+        assert_eq!(error("add #1, #2").line, 1);
+        assert_eq!(error("add #1, #2, #3").line, 1);
+        assert_eq!(error("\n\njt #1, #nowhere").line, 3);
+        assert_eq!(error("a: hlt\na: hlt").line, 2);
+        assert_eq!(error("out 5").line, 1);
+    }
+}
@@ -0,0 +1,45 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use intcode::VM;
+use intcode::adventure::{Adventure, IntcodeConsole};
+
+const DEFAULT_SCRIPT: &str = "explore\nsolve\n";
+
+fn read(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(1);
+        },
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let path = match args.next() {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: adventure <program> [script]");
+            process::exit(1);
+        },
+    };
+
+    let program = intcode::parse(&read(&path));
+    let script = args.next().map(|path| read(&path)).unwrap_or_else(|| DEFAULT_SCRIPT.to_owned());
+    let mut adventure = Adventure::new(IntcodeConsole::new(VM::new(program)));
+
+    let result = adventure.run_script(&script);
+
+    for entry in adventure.transcript() {
+        println!("{}", entry.trim_end());
+    }
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,56 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::process;
+
+use intcode::{VM, RunState};
+use intcode::ascii::AsciiVM;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: ascii <program>");
+            process::exit(1);
+        },
+    };
+
+    let input = match fs::read_to_string(&path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(1);
+        },
+    };
+
+    let mut vm = AsciiVM::new(VM::new(intcode::parse(&input)));
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        let run = match vm.run() {
+            Ok(run) => run,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            },
+        };
+
+        for line in &run.lines {
+            println!("{}", line);
+        }
+
+        for value in &run.values {
+            println!("[{}]", value);
+        }
+
+        if run.state == RunState::Halted {
+            return;
+        }
+
+        match lines.next() {
+            Some(Ok(line)) => vm.send_line(&line),
+            _ => return,
+        }
+    }
+}
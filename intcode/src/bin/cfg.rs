@@ -0,0 +1,29 @@
+use std::env;
+use std::fs;
+use std::process;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: cfg <program>");
+            process::exit(1);
+        },
+    };
+
+    let input = match fs::read_to_string(&path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(1);
+        },
+    };
+
+    let analysis = intcode::analysis::analyze(&intcode::parse(&input));
+
+    for modification in &analysis.self_modifications {
+        eprintln!("self-modifying write at {} to {}", modification.addr, modification.target);
+    }
+
+    print!("{}", analysis.to_dot());
+}
@@ -0,0 +1,47 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use intcode::VM;
+use intcode::debugger::Debugger;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: debugger <program>");
+            process::exit(1);
+        },
+    };
+
+    let input = match fs::read_to_string(&path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(1);
+        },
+    };
+
+    let mut debugger = Debugger::new(VM::new(intcode::parse(&input)));
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    println!("{}", debugger.execute("disas").unwrap_or_default());
+
+    while !debugger.has_quit() {
+        print!("(icdb) ");
+        io::stdout().flush().ok();
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+
+        match debugger.execute(&line) {
+            Ok(output) if output.is_empty() => {},
+            Ok(output) => println!("{}", output),
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+}
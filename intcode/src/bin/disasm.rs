@@ -0,0 +1,25 @@
+use std::env;
+use std::fs;
+use std::process;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: disasm <program>");
+            process::exit(1);
+        },
+    };
+
+    let input = match fs::read_to_string(&path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(1);
+        },
+    };
+
+    let mem = intcode::parse(&input);
+
+    print!("{}", intcode::disasm::disassemble(&mem));
+}
@@ -0,0 +1,24 @@
+use std::env;
+use std::process;
+
+fn main() {
+    let mut args = env::args().skip(1).map(|arg| arg.parse::<u64>());
+
+    let (seed, count) = match (args.next(), args.next()) {
+        (None, None) => (0, 100_000),
+        (Some(Ok(seed)), None) => (seed, 100_000),
+        (Some(Ok(seed)), Some(Ok(count))) => (seed, count as usize),
+        _ => {
+            eprintln!("usage: fuzz [seed] [count]");
+            process::exit(1);
+        },
+    };
+
+    match intcode::fuzz::fuzz(seed, count, 1000) {
+        Ok(()) => println!("{} cases passed", count),
+        Err(mismatch) => {
+            println!("{}", mismatch);
+            process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,59 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
+use intcode::VM;
+
+const TOP: usize = 20;
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let path = match args.next() {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: profile <program> [folded-output] < inputs");
+            process::exit(1);
+        },
+    };
+
+    let input = match fs::read_to_string(&path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(1);
+        },
+    };
+
+    let mut inputs = String::new();
+    io::stdin().read_to_string(&mut inputs).ok();
+
+    let mut vm = VM::new(intcode::parse(&input));
+    vm.set_profiling(true);
+
+    for value in inputs.split(|c: char| c == ',' || c.is_whitespace()).filter(|value| !value.is_empty()) {
+        match value.parse() {
+            Ok(value) => vm.queue_input(value),
+            Err(_) => {
+                eprintln!("invalid input: {}", value);
+                process::exit(1);
+            },
+        }
+    }
+
+    if let Err(err) = vm.run() {
+        eprintln!("error: {}", err);
+    }
+
+    let profile = vm.take_profile().expect("profiling enabled");
+
+    print!("{}", profile.report(&vm, TOP));
+
+    if let Some(path) = args.next() {
+        if let Err(err) = fs::write(&path, profile.folded()) {
+            eprintln!("failed to write {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::{VM, Op, VmError};
+use crate::disasm::Instruction;
+
+const HISTORY_LIMIT: usize = 100_000;
+
+const HELP: &str = "\
+commands:
+  s, step [n]           execute n instructions (default 1)
+  back [n]              undo the last n instructions (default 1)
+  c, continue           run until a breakpoint, watchpoint, input request or halt
+  finish                run until the current frame returns (relative base drops)
+  b, break <addr>       set a breakpoint on ip
+  d, delete <addr>      remove a breakpoint
+  w, watch <addr>       stop when the value at addr changes
+  unwatch <addr>        remove a watchpoint
+  info                  list breakpoints and watchpoints
+  r, regs               print ip and relative base
+  x, mem <addr> [len]   dump memory
+  disas [addr] [n]      disassemble n instructions (default: at ip)
+  i, input <value>...   queue input values
+  outputs               print all outputs so far
+  q, quit               exit the debugger";
+
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint { addr: usize, old: isize, new: isize },
+    FrameReturned,
+    NeedsInput,
+    Halted,
+    Error(VmError),
+}
+
+enum RunMode {
+    Step(usize),
+    Continue,
+    Finish,
+}
+
+pub struct Debugger<'a> {
+    vm: VM<'a>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    reported_outputs: usize,
+    last_command: Option<String>,
+    quit: bool,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(mut vm: VM<'a>) -> Self {
+        if vm.history_limit() == 0 {
+            vm.set_history_limit(HISTORY_LIMIT);
+        }
+
+        Self {
+            vm,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            reported_outputs: 0,
+            last_command: None,
+            quit: false,
+        }
+    }
+
+    pub fn vm(&self) -> &VM<'a> {
+        &self.vm
+    }
+
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn add_watchpoint(&mut self, addr: usize) {
+        self.watchpoints.insert(addr);
+    }
+
+    pub fn step(&mut self, count: usize) -> Stop {
+        self.resume(RunMode::Step(count))
+    }
+
+    // Returns how many instructions were actually undone
+    pub fn step_back(&mut self, count: usize) -> usize {
+        let undone = (0..count).take_while(|_| self.vm.step_back()).count();
+        self.reported_outputs = self.reported_outputs.min(self.vm.outputs().len());
+        undone
+    }
+
+    pub fn cont(&mut self) -> Stop {
+        self.resume(RunMode::Continue)
+    }
+
+    pub fn finish(&mut self) -> Stop {
+        self.resume(RunMode::Finish)
+    }
+
+    fn resume(&mut self, mode: RunMode) -> Stop {
+        // Time spent at the prompt doesn't count against a time limit
+        self.vm.reset_time_limit();
+        let start_base = self.vm.relative_base();
+        let mut steps = 0;
+
+        loop {
+            let ip = self.vm.ip();
+
+            if steps > 0 && self.breakpoints.contains(&ip) {
+                return Stop::Breakpoint(ip);
+            }
+
+            let op_code = match self.vm.next_op_code() {
+                Ok(op_code) => op_code,
+                Err(err) => return Stop::Error(err),
+            };
+
+            match op_code.op() {
+                Op::Halt => return Stop::Halted,
+                Op::ReadInput if !self.vm.has_input() => return Stop::NeedsInput,
+                _ => {},
+            }
+
+            let watched = self.watchpoints
+                .iter()
+                .map(|&addr| (addr, self.vm.read(addr)))
+                .collect::<BTreeMap<_, _>>();
+
+            if let Err(err) = self.vm.execute(&op_code) {
+                return Stop::Error(err);
+            }
+
+            steps += 1;
+
+            for (&addr, &old) in &watched {
+                let new = self.vm.read(addr);
+
+                if new != old {
+                    return Stop::Watchpoint { addr, old, new };
+                }
+            }
+
+            match mode {
+                RunMode::Step(count) if steps >= count => return Stop::Stepped,
+                RunMode::Finish if self.vm.relative_base() < start_base => return Stop::FrameReturned,
+                _ => {},
+            }
+        }
+    }
+
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = match line.trim() {
+            "" => self.last_command.clone().unwrap_or_default(),
+            line => line.to_owned(),
+        };
+
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(String::new()),
+        };
+        let args = words
+            .map(|word| word.parse::<isize>().map_err(|_| format!("invalid number `{}`", word)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.last_command = Some(line.clone());
+
+        let stop = match command {
+            "s" | "step" => self.step(count_arg(&args, 0, 1)?),
+            "back" => {
+                let undone = self.step_back(count_arg(&args, 0, 1)?);
+                return Ok(format!("stepped back {} instruction(s)\n{}", undone, self.disassemble(self.vm.ip(), 1)));
+            },
+            "c" | "continue" => self.cont(),
+            "finish" => self.finish(),
+            "b" | "break" => {
+                let addr = addr_arg(&args, 0)?;
+                self.breakpoints.insert(addr);
+                return Ok(format!("breakpoint at {}", addr));
+            },
+            "d" | "delete" => {
+                let addr = addr_arg(&args, 0)?;
+                self.breakpoints.remove(&addr);
+                return Ok(format!("removed breakpoint at {}", addr));
+            },
+            "w" | "watch" => {
+                let addr = addr_arg(&args, 0)?;
+                self.watchpoints.insert(addr);
+                return Ok(format!("watching [{}] = {}", addr, self.vm.read(addr)));
+            },
+            "unwatch" => {
+                let addr = addr_arg(&args, 0)?;
+                self.watchpoints.remove(&addr);
+                return Ok(format!("removed watchpoint on [{}]", addr));
+            },
+            "info" => return Ok(self.info()),
+            "r" | "regs" => return Ok(self.registers()),
+            "x" | "mem" => return Ok(self.dump(addr_arg(&args, 0)?, count_arg(&args, 1, 8)?)),
+            "disas" => {
+                let addr = match args.first() {
+                    Some(_) => addr_arg(&args, 0)?,
+                    None => self.vm.ip(),
+                };
+                return Ok(self.disassemble(addr, count_arg(&args, 1, 5)?));
+            },
+            "i" | "input" => {
+                if args.is_empty() {
+                    return Err("input needs at least one value".to_owned());
+                }
+
+                for &value in &args {
+                    self.vm.queue_input(value);
+                }
+
+                return Ok(format!("queued {} input(s)", args.len()));
+            },
+            "outputs" => return Ok(format!("{:?}", self.vm.outputs())),
+            "h" | "help" => return Ok(HELP.to_owned()),
+            "q" | "quit" => {
+                self.quit = true;
+                return Ok(String::new());
+            },
+            _ => return Err(format!("unknown command `{}`, try `help`", command)),
+        };
+
+        Ok(self.report(&stop))
+    }
+
+    fn report(&mut self, stop: &Stop) -> String {
+        let mut out = String::new();
+
+        for output in &self.vm.outputs()[self.reported_outputs..] {
+            writeln!(out, "output: {}", output).unwrap();
+        }
+        self.reported_outputs = self.vm.outputs().len();
+
+        match *stop {
+            Stop::Stepped | Stop::FrameReturned => {},
+            Stop::Breakpoint(addr) => writeln!(out, "breakpoint at {}", addr).unwrap(),
+            Stop::Watchpoint { addr, old, new } => writeln!(out, "watchpoint [{}]: {} -> {}", addr, old, new).unwrap(),
+            Stop::NeedsInput => writeln!(out, "waiting for input, use `input <value>`").unwrap(),
+            Stop::Halted => writeln!(out, "halted").unwrap(),
+            Stop::Error(err) => writeln!(out, "error: {}", err).unwrap(),
+        }
+
+        out + &self.disassemble(self.vm.ip(), 1)
+    }
+
+    fn registers(&self) -> String {
+        format!("ip = {}\nrelative_base = {}", self.vm.ip(), self.vm.relative_base())
+    }
+
+    fn info(&self) -> String {
+        format!("breakpoints: {:?}\nwatchpoints: {:?}", self.breakpoints, self.watchpoints)
+    }
+
+    fn dump(&self, addr: usize, len: usize) -> String {
+        let mut out = String::new();
+
+        for row in (addr .. addr + len).step_by(8) {
+            let end = (row + 8).min(addr + len);
+            let cells = (row..end)
+                .map(|addr| self.vm.read(addr).to_string())
+                .collect::<Vec<_>>();
+
+            writeln!(out, "{:>6}: {}", row, cells.join(" ")).unwrap();
+        }
+
+        out.trim_end().to_owned()
+    }
+
+    fn disassemble(&self, mut addr: usize, count: usize) -> String {
+        let mut out = Vec::new();
+
+        for _ in 0..count {
+            let marker = if addr == self.vm.ip() { "=>" } else { "  " };
+
+            match Instruction::decode_with(addr, |addr| Some(self.vm.read(addr))) {
+                Some(instruction) => {
+                    out.push(format!("{} {:>6}  {}", marker, addr, instruction));
+                    addr = instruction.next_addr();
+                },
+                None => {
+                    out.push(format!("{} {:>6}  .data {}", marker, addr, self.vm.read(addr)));
+                    addr += 1;
+                },
+            }
+        }
+
+        out.join("\n")
+    }
+}
+
+fn addr_arg(args: &[isize], index: usize) -> Result<usize, String> {
+    match args.get(index) {
+        Some(&addr) if addr >= 0 => Ok(addr as usize),
+        Some(addr) => Err(format!("invalid address {}", addr)),
+        None => Err("missing address".to_owned()),
+    }
+}
+
+fn count_arg(args: &[isize], index: usize, default: usize) -> Result<usize, String> {
+    match args.get(index) {
+        Some(&count) if count > 0 => Ok(count as usize),
+        Some(count) => Err(format!("invalid count {}", count)),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn debugger(source: &str) -> Debugger<'static> {
+        Debugger::new(VM::new(assemble(source).unwrap()))
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut debugger = debugger("
+            add #1, #2, [10]
+            out [10]
+            hlt
+        ");
+
+        debugger.add_breakpoint(4);
+
+        assert_eq!(debugger.cont(), Stop::Breakpoint(4));
+        assert_eq!(debugger.cont(), Stop::Halted);
+        assert_eq!(debugger.vm().outputs(), [3]);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut debugger = debugger("
+            add #1, #0, [x]
+            add #1, #1, [x]
+            hlt
+            x: .data 1
+        ");
+
+        debugger.add_watchpoint(9);
+
+        assert_eq!(debugger.cont(), Stop::Watchpoint { addr: 9, old: 1, new: 2 });
+        assert_eq!(debugger.vm().ip(), 8);
+    }
+
+    #[test]
+    fn finish() {
+        let mut debugger = debugger("
+            arb #10
+            add #1, #1, [0]
+            arb #-10
+            hlt
+        ");
+
+        debugger.step(1);
+
+        assert_eq!(debugger.finish(), Stop::FrameReturned);
+        assert_eq!(debugger.vm().ip(), 8);
+    }
+
+    #[test]
+    fn input_injection() {
+        let mut debugger = debugger("
+            in [0]
+            out [0]
+            hlt
+        ");
+
+        assert_eq!(debugger.cont(), Stop::NeedsInput);
+
+        debugger.execute("input 7").unwrap();
+        let report = debugger.execute("continue").unwrap();
+
+        assert!(report.starts_with("output: 7\nhalted\n"));
+    }
+
+    #[test]
+    fn step_back() {
+        let mut debugger = debugger("
+            add #1, #0, [x]
+            out [x]
+            hlt
+            x: .data 5
+        ");
+
+        assert_eq!(debugger.cont(), Stop::Halted);
+        assert_eq!(debugger.step_back(1), 1);
+        assert_eq!(debugger.vm().read(7), 1);
+        assert!(debugger.vm().outputs().is_empty());
+
+        let report = debugger.execute("back 10").unwrap();
+
+        assert!(report.starts_with("stepped back 1 instruction(s)\n"));
+        assert_eq!(debugger.vm().read(7), 5);
+        assert_eq!(debugger.execute("s 2").unwrap().lines().next(), Some("output: 1"));
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::{OpCode, Op, Mode};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub addr: usize,
+    pub op_code: OpCode,
+    pub args: Vec<isize>,
+}
+
+impl Instruction {
+    pub fn decode(mem: &[isize], addr: usize) -> Option<Self> {
+        Self::decode_with(addr, |addr| mem.get(addr).copied())
+    }
+
+    pub fn decode_with(addr: usize, read: impl Fn(usize) -> Option<isize>) -> Option<Self> {
+        let op_code = OpCode::parse(read(addr)?).ok()?;
+        let op = op_code.op();
+        let arg_count = op.arg_count();
+        let args = (addr + 1 .. addr + 1 + arg_count)
+            .map(&read)
+            .collect::<Option<Vec<_>>>()?;
+
+        if op.writes() && op_code.mode(arg_count - 1) == Mode::Immediate {
+            return None;
+        }
+
+        Some(Self { addr, op_code, args })
+    }
+
+    pub fn op(&self) -> Op {
+        self.op_code.op()
+    }
+
+    pub fn size(&self) -> usize {
+        1 + self.args.len()
+    }
+
+    pub fn next_addr(&self) -> usize {
+        self.addr + self.size()
+    }
+
+    pub fn jump_target(&self) -> Option<usize> {
+        if !self.op().is_jump() || self.op_code.mode(1) != Mode::Immediate {
+            return None;
+        }
+
+        match self.args[1] {
+            target if target >= 0 => Some(target as usize),
+            _ => None,
+        }
+    }
+
+    fn fmt_with_labels(&self, f: &mut fmt::Formatter, labels: &BTreeSet<usize>) -> fmt::Result {
+        write!(f, "{}", self.op().mnemonic())?;
+
+        for (index, &arg) in self.args.iter().enumerate() {
+            let sep = if index == 0 { " " } else { ", " };
+            let is_target = index == 1 && matches!(self.jump_target(), Some(target) if labels.contains(&target));
+
+            match self.op_code.mode(index) {
+                Mode::Position => write!(f, "{}[{}]", sep, arg)?,
+                Mode::Immediate if is_target => write!(f, "{}#{}", sep, label(arg as usize))?,
+                Mode::Immediate => write!(f, "{}#{}", sep, arg)?,
+                Mode::Relative => write!(f, "{}rel[{:+}]", sep, arg)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with_labels(f, &BTreeSet::new())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Instruction(Instruction),
+    Data { addr: usize, value: isize },
+}
+
+impl Item {
+    pub fn addr(&self) -> usize {
+        match self {
+            Item::Instruction(instruction) => instruction.addr,
+            Item::Data { addr, .. } => *addr,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub items: Vec<Item>,
+    pub labels: BTreeSet<usize>,
+}
+
+pub fn disassemble(mem: &[isize]) -> Listing {
+    let mut items = Vec::new();
+    let mut addr = 0;
+
+    while addr < mem.len() {
+        match Instruction::decode(mem, addr) {
+            Some(instruction) => {
+                addr = instruction.next_addr();
+                items.push(Item::Instruction(instruction));
+            },
+            None => {
+                items.push(Item::Data { addr, value: mem[addr] });
+                addr += 1;
+            },
+        }
+    }
+
+    let starts = items.iter().map(Item::addr).collect::<BTreeSet<_>>();
+    let labels = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Instruction(instruction) => instruction.jump_target(),
+            Item::Data { .. } => None,
+        })
+        .filter(|target| starts.contains(target))
+        .collect();
+
+    Listing { items, labels }
+}
+
+pub fn label(addr: usize) -> String {
+    format!("L{}", addr)
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in &self.items {
+            let addr = item.addr();
+
+            if self.labels.contains(&addr) {
+                writeln!(f, "{}:", label(addr))?;
+            }
+
+            write!(f, "{:>6}  ", addr)?;
+
+            match item {
+                Item::Instruction(instruction) => instruction.fmt_with_labels(f, &self.labels)?,
+                Item::Data { value, .. } => write!(f, ".data {}", value)?,
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes() {
+        let mem = [21_101, 100, 5, 2, 99];
+        let instruction = Instruction::decode(&mem, 0).unwrap();
+
+        assert_eq!(instruction.to_string(), "add #100, #5, rel[+2]");
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let mem = [1105, 1, 4, 77, 4, 0, 99, 55];
+        let listing = disassemble(&mem).to_string();
+        let lines = listing.lines().map(str::trim).collect::<Vec<_>>();
+
+        assert_eq!(lines, [
+            "0  jt #1, #L4",
+            "3  .data 77",
+            "L4:",
+            "4  out [0]",
+            "6  hlt",
+            "7  .data 55",
+        ]);
+    }
+
+    #[test]
+    fn truncated_instruction() {
+        let mem = [1, 0, 0];
+        let listing = disassemble(&mem);
+
+        assert!(listing.items.iter().all(|item| matches!(item, Item::Data { .. })));
+    }
+}
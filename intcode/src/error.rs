@@ -0,0 +1,236 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VmError {
+    InvalidOpcode { ip: usize, code: isize },
+    InvalidMode { ip: usize, mode: isize },
+    NegativeAddress { ip: usize, addr: isize },
+    WriteToImmediate { ip: usize },
+    InputExhausted { ip: usize },
+    Overflow { ip: usize },
+    LimitExceeded { ip: usize, limit: Limit },
+    InfiniteLoop { ip: usize },
+}
+
+// Which of the limits configured on a VM a run exceeded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+    Instructions,
+    Memory,
+    Time,
+}
+
+impl VmError {
+    pub fn ip(&self) -> usize {
+        match *self {
+            VmError::InvalidOpcode { ip, .. } => ip,
+            VmError::InvalidMode { ip, .. } => ip,
+            VmError::NegativeAddress { ip, .. } => ip,
+            VmError::WriteToImmediate { ip } => ip,
+            VmError::InputExhausted { ip } => ip,
+            VmError::Overflow { ip } => ip,
+            VmError::LimitExceeded { ip, .. } => ip,
+            VmError::InfiniteLoop { ip } => ip,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VmError::InvalidOpcode { ip, code } => write!(f, "invalid opcode {} at ip {}", code, ip),
+            VmError::InvalidMode { ip, mode } => write!(f, "invalid mode {} at ip {}", mode, ip),
+            VmError::NegativeAddress { ip, addr } => write!(f, "negative address {} at ip {}", addr, ip),
+            VmError::WriteToImmediate { ip } => write!(f, "write to immediate argument at ip {}", ip),
+            VmError::InputExhausted { ip } => write!(f, "input exhausted at ip {}", ip),
+            VmError::Overflow { ip } => write!(f, "arithmetic overflow at ip {}", ip),
+            VmError::LimitExceeded { ip, limit } => write!(f, "{} limit exceeded at ip {}", limit, ip),
+            VmError::InfiniteLoop { ip } => write!(f, "infinite loop detected at ip {}", ip),
+        }
+    }
+}
+
+impl Error for VmError {}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Instructions => write!(f, "instruction"),
+            Limit::Memory => write!(f, "memory"),
+            Limit::Time => write!(f, "time"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidOpcode(isize),
+    InvalidMode(isize),
+}
+
+impl DecodeError {
+    pub fn at(self, ip: usize) -> VmError {
+        match self {
+            DecodeError::InvalidOpcode(code) => VmError::InvalidOpcode { ip, code },
+            DecodeError::InvalidMode(mode) => VmError::InvalidMode { ip, mode },
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::InvalidOpcode(code) => write!(f, "invalid opcode {}", code),
+            DecodeError::InvalidMode(mode) => write!(f, "invalid mode {}", mode),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    UnsupportedVersion(String),
+    Malformed { line: usize, message: String },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "io error: {}", err),
+            SaveError::UnsupportedVersion(version) => write!(f, "unsupported save version `{}`", version),
+            SaveError::Malformed { line, message } => write!(f, "malformed save at line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for SaveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SaveError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    UnsupportedVersion(u8),
+    Malformed { offset: usize, message: String },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Io(err) => write!(f, "io error: {}", err),
+            TraceError::UnsupportedVersion(version) => write!(f, "unsupported trace version {}", version),
+            TraceError::Malformed { offset, message } => write!(f, "malformed trace at byte {}: {}", offset, message),
+        }
+    }
+}
+
+impl Error for TraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TraceError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(err: io::Error) -> Self {
+        TraceError::Io(err)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SymbolicError {
+    Vm(VmError),
+    StepLimit,
+    MissingOutput(usize),
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolicError::Vm(err) => write!(f, "{}", err),
+            SymbolicError::StepLimit => write!(f, "step limit exceeded"),
+            SymbolicError::MissingOutput(index) => write!(f, "the program produced no output #{}", index),
+        }
+    }
+}
+
+impl Error for SymbolicError {}
+
+impl From<VmError> for SymbolicError {
+    fn from(err: VmError) -> Self {
+        SymbolicError::Vm(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdventureError {
+    Vm(VmError),
+    Halted,
+    Stuck,
+    UnexpectedOutput(String),
+    UnknownRoom(String),
+    NoCheckpoint,
+    NoSolution,
+}
+
+impl fmt::Display for AdventureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdventureError::Vm(err) => write!(f, "{}", err),
+            AdventureError::Halted => write!(f, "the program halted unexpectedly"),
+            AdventureError::Stuck => write!(f, "the program stopped responding"),
+            AdventureError::UnexpectedOutput(output) => write!(f, "unexpected output: {}", output),
+            AdventureError::UnknownRoom(room) => write!(f, "no known path to room `{}`", room),
+            AdventureError::NoCheckpoint => write!(f, "no pressure-sensitive checkpoint found"),
+            AdventureError::NoSolution => write!(f, "no item combination passes the checkpoint"),
+        }
+    }
+}
+
+impl Error for AdventureError {}
+
+impl From<VmError> for AdventureError {
+    fn from(err: VmError) -> Self {
+        AdventureError::Vm(err)
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::VecDeque;
+
+use crate::{OpCode, Op, Mode, RunState, VmError, OverflowPolicy};
+
+#[derive(Debug, Copy, Clone)]
+struct Decoded {
+    op: Op,
+    modes: [Mode; 3],
+}
+
+impl Decoded {
+    fn parse(code: isize) -> Result<Self, crate::DecodeError> {
+        let op_code = OpCode::parse(code)?;
+
+        Ok(Self {
+            op: op_code.op(),
+            modes: [op_code.mode(0), op_code.mode(1), op_code.mode(2)],
+        })
+    }
+}
+
+pub struct FastVM {
+    mem: Vec<isize>,
+    // Decoded opcodes by address, cleared whenever the cell is written
+    cache: Vec<Option<Decoded>>,
+    ip: usize,
+    relative_base: isize,
+    inputs: VecDeque<isize>,
+    outputs: Vec<isize>,
+    overflow_policy: OverflowPolicy,
+}
+
+impl FastVM {
+    pub fn new(mem: impl Into<Vec<isize>>) -> Self {
+        let mem = mem.into();
+
+        Self {
+            cache: vec![None; mem.len()],
+            mem,
+            ip: 0,
+            relative_base: 0,
+            inputs: VecDeque::new(),
+            outputs: Vec::new(),
+            overflow_policy: OverflowPolicy::default(),
+        }
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    pub fn add_input(&mut self, value: isize) {
+        self.inputs.push_back(value);
+    }
+
+    pub fn outputs(&self) -> &[isize] {
+        &self.outputs
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    pub fn mem(&self) -> &[isize] {
+        &self.mem
+    }
+
+    pub fn read(&self, addr: usize) -> isize {
+        self.mem.get(addr).copied().unwrap_or(0)
+    }
+
+    pub fn write(&mut self, addr: usize, value: isize) {
+        if addr >= self.mem.len() {
+            self.mem.resize(addr + 1, 0);
+            self.cache.resize(addr + 1, None);
+        }
+
+        self.mem[addr] = value;
+        self.cache[addr] = None;
+    }
+
+    pub fn run(&mut self) -> Result<RunState, VmError> {
+        loop {
+            let Decoded { op, modes } = self.decode()?;
+
+            match op {
+                Op::Add => {
+                    let a = self.load(1, modes[0])?;
+                    let b = self.load(2, modes[1])?;
+                    let value = self.overflow_policy.add(self.ip, a, b)?;
+                    self.store(3, modes[2], value)?;
+                    self.ip += 4;
+                },
+                Op::Mul => {
+                    let a = self.load(1, modes[0])?;
+                    let b = self.load(2, modes[1])?;
+                    let value = self.overflow_policy.mul(self.ip, a, b)?;
+                    self.store(3, modes[2], value)?;
+                    self.ip += 4;
+                },
+                Op::ReadInput => {
+                    let value = match self.inputs.pop_front() {
+                        Some(value) => value,
+                        None => return Ok(RunState::NeedsInput),
+                    };
+
+                    self.store(1, modes[0], value)?;
+                    self.ip += 2;
+                },
+                Op::WriteOutput => {
+                    let value = self.load(1, modes[0])?;
+                    self.outputs.push(value);
+                    self.ip += 2;
+                },
+                Op::JumpIfTrue | Op::JumpIfFalse => {
+                    let cond = self.load(1, modes[0])?;
+                    let target = self.load(2, modes[1])?;
+
+                    if (cond != 0) == (op == Op::JumpIfTrue) {
+                        self.ip = self.addr(target)?;
+                    } else {
+                        self.ip += 3;
+                    }
+                },
+                Op::LessThan => {
+                    let a = self.load(1, modes[0])?;
+                    let b = self.load(2, modes[1])?;
+                    self.store(3, modes[2], (a < b) as isize)?;
+                    self.ip += 4;
+                },
+                Op::Equals => {
+                    let a = self.load(1, modes[0])?;
+                    let b = self.load(2, modes[1])?;
+                    self.store(3, modes[2], (a == b) as isize)?;
+                    self.ip += 4;
+                },
+                Op::AdjustRelativeBase => {
+                    let adjustment = self.load(1, modes[0])?;
+                    self.relative_base = self.overflow_policy.add(self.ip, self.relative_base, adjustment)?;
+                    self.ip += 2;
+                },
+                Op::Halt => return Ok(RunState::Halted),
+            }
+        }
+    }
+
+    fn decode(&mut self) -> Result<Decoded, VmError> {
+        let ip = self.ip;
+
+        if let Some(Some(decoded)) = self.cache.get(ip) {
+            return Ok(*decoded);
+        }
+
+        let decoded = Decoded::parse(self.read(ip)).map_err(|err| err.at(ip))?;
+
+        if ip >= self.cache.len() {
+            self.cache.resize(ip + 1, None);
+        }
+
+        self.cache[ip] = Some(decoded);
+
+        Ok(decoded)
+    }
+
+    fn addr(&self, addr: isize) -> Result<usize, VmError> {
+        if addr < 0 {
+            return Err(VmError::NegativeAddress { ip: self.ip, addr });
+        }
+
+        Ok(addr as usize)
+    }
+
+    fn load(&self, index: usize, mode: Mode) -> Result<isize, VmError> {
+        let arg = self.read(self.ip + index);
+
+        let addr = match mode {
+            Mode::Immediate => return Ok(arg),
+            Mode::Position => arg,
+            Mode::Relative => self.overflow_policy.add(self.ip, self.relative_base, arg)?,
+        };
+
+        Ok(self.read(self.addr(addr)?))
+    }
+
+    fn store(&mut self, index: usize, mode: Mode, value: isize) -> Result<(), VmError> {
+        let arg = self.read(self.ip + index);
+
+        let addr = match mode {
+            Mode::Immediate => return Err(VmError::WriteToImmediate { ip: self.ip }),
+            Mode::Position => arg,
+            Mode::Relative => self.overflow_policy.add(self.ip, self.relative_base, arg)?,
+        };
+
+        let addr = self.addr(addr)?;
+        self.write(addr, value);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VM;
+    use crate::asm::assemble;
+
+    fn assert_same(program: &[isize], inputs: &[isize]) {
+        let mut vm = VM::new(program);
+        let mut fast = FastVM::new(program);
+
+        for &input in inputs {
+            vm.add_input(input);
+            fast.add_input(input);
+        }
+
+        vm.run().unwrap();
+
+        assert_eq!(fast.run(), Ok(RunState::Halted));
+        assert_eq!(fast.outputs(), vm.outputs());
+        assert_eq!(fast.ip(), vm.ip());
+        assert_eq!(fast.relative_base(), vm.relative_base());
+
+        for addr in 0..fast.mem().len() {
+            assert_eq!(fast.read(addr), vm.read(addr), "memory differs at {}", addr);
+        }
+    }
+
+    #[test]
+    fn matches_vm() {
+        let quine = [109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        assert_same(&quine, &[]);
+
+        let compare = [
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31,
+            1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104,
+            999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99,
+        ];
+
+        for input in 6..=10 {
+            assert_same(&compare, &[input]);
+        }
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        let program = assemble("
+            start:
+            x:  out #1
+                add #99, #0, [x]
+                jt #1, #start
+        ").unwrap();
+
+        let mut fast = FastVM::new(program);
+
+        assert_eq!(fast.run(), Ok(RunState::Halted));
+        assert_eq!(fast.outputs(), [1]);
+    }
+
+    #[test]
+    fn needs_input() {
+        let mut fast = FastVM::new(vec![3, 5, 4, 5, 99, 0]);
+
+        assert_eq!(fast.run(), Ok(RunState::NeedsInput));
+        assert_eq!(fast.ip(), 0);
+
+        fast.add_input(3);
+
+        assert_eq!(fast.run(), Ok(RunState::Halted));
+        assert_eq!(fast.outputs(), [3]);
+    }
+}
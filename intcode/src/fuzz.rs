@@ -0,0 +1,583 @@
+use std::fmt;
+
+use crate::{VM, Op, Mode, VmError, Limit};
+
+// Cases writing beyond this are skipped rather than compared, so that a
+// corrupted address can't make either side allocate gigabytes
+pub const MEMORY_LIMIT: usize = 1 << 16;
+
+// xorshift64*, good enough for test cases and reproducible by seed
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must never be zero
+        Self { state: (seed ^ 0x9e37_79b9_7f4a_7c15) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    // Inclusive on both ends
+    pub fn range(&mut self, low: isize, high: isize) -> isize {
+        low + self.below((high - low + 1) as usize) as isize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub program: Vec<isize>,
+    pub inputs: Vec<isize>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Halted,
+    Error(VmError),
+    StepLimit,
+}
+
+// Final state of a run, memory without trailing zeros
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub outcome: Outcome,
+    pub outputs: Vec<isize>,
+    pub ip: usize,
+    pub relative_base: isize,
+    pub mem: Vec<isize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub case: Case,
+    pub expected: Report,
+    // None when the VM wrote beyond MEMORY_LIMIT
+    pub actual: Option<Report>,
+}
+
+const OPS: [Op; 10] = [
+    Op::Add,
+    Op::Mul,
+    Op::ReadInput,
+    Op::WriteOutput,
+    Op::JumpIfTrue,
+    Op::JumpIfFalse,
+    Op::LessThan,
+    Op::Equals,
+    Op::AdjustRelativeBase,
+    Op::Halt,
+];
+
+const MODES: [Mode; 3] = [Mode::Position, Mode::Immediate, Mode::Relative];
+
+// A short program followed by a halt and a data area. Jumps mostly target
+// instruction starts, most addresses point into the data area and relative
+// base adjustments are small immediates, so the relative base stays small.
+pub fn generate(rng: &mut Rng) -> Case {
+    let count = 1 + rng.below(12);
+    let mut shapes = Vec::with_capacity(count + 1);
+
+    for _ in 0..count {
+        let op = OPS[rng.below(OPS.len() - 1)];
+        let mut modes = (0..op.arg_count())
+            .map(|_| MODES[rng.below(MODES.len())])
+            .collect::<Vec<_>>();
+
+        // Mostly well-formed, but immediate writes must fail too
+        if op.writes() && modes[op.arg_count() - 1] == Mode::Immediate && !rng.chance(5) {
+            modes[op.arg_count() - 1] = Mode::Position;
+        }
+
+        if op == Op::AdjustRelativeBase {
+            modes[0] = Mode::Immediate;
+        }
+
+        shapes.push((op, modes));
+    }
+
+    shapes.push((Op::Halt, Vec::new()));
+
+    let mut starts = Vec::with_capacity(shapes.len());
+    let mut code_len = 0;
+
+    for (op, _) in &shapes {
+        starts.push(code_len);
+        code_len += 1 + op.arg_count();
+    }
+
+    let data_len = 8;
+    let len = code_len + data_len;
+    let mut program = Vec::with_capacity(len);
+
+    for (op, modes) in &shapes {
+        let mut code = op.code();
+        let mut factor = 100;
+
+        for mode in modes {
+            code += mode.code() * factor;
+            factor *= 10;
+        }
+
+        program.push(code);
+
+        for (index, &mode) in modes.iter().enumerate() {
+            let arg = match mode {
+                Mode::Immediate if *op == Op::AdjustRelativeBase => rng.range(-4, 8),
+                Mode::Immediate if op.is_jump() && index == 1 && !rng.chance(10) => {
+                    starts[rng.below(starts.len())] as isize
+                },
+                Mode::Immediate => value(rng),
+                Mode::Position if rng.chance(70) => (code_len + rng.below(data_len)) as isize,
+                Mode::Position => rng.below(len) as isize,
+                Mode::Relative => rng.range(-4, len as isize),
+            };
+
+            program.push(arg);
+        }
+    }
+
+    while program.len() < len {
+        program.push(value(rng));
+    }
+
+    let inputs = (0..rng.below(5)).map(|_| value(rng)).collect();
+
+    Case { program, inputs }
+}
+
+fn value(rng: &mut Rng) -> isize {
+    match rng.below(20) {
+        0 => isize::MAX - rng.range(0, 2),
+        1 => isize::MIN + rng.range(0, 2),
+        2 => rng.range(-1 << 32, 1 << 32),
+        _ => rng.range(-10, 20),
+    }
+}
+
+// Deliberately naive interpreter to check the VM against, sharing no code
+// with it
+struct Reference {
+    mem: Vec<isize>,
+    inputs: Vec<isize>,
+    outputs: Vec<isize>,
+    ip: usize,
+    relative_base: isize,
+}
+
+// Why the reference stopped executing
+enum Stop {
+    Halt,
+    Error(VmError),
+    OutOfBounds,
+}
+
+impl From<VmError> for Stop {
+    fn from(err: VmError) -> Self {
+        Stop::Error(err)
+    }
+}
+
+impl Reference {
+    fn read(&self, addr: usize) -> isize {
+        if addr < self.mem.len() { self.mem[addr] } else { 0 }
+    }
+
+    fn mode(&self, index: usize) -> isize {
+        self.read(self.ip) / 10isize.pow(index as u32 + 2) % 10
+    }
+
+    fn param(&self, index: usize) -> isize {
+        self.read(self.ip + 1 + index)
+    }
+
+    fn address(&self, index: usize) -> Result<usize, VmError> {
+        let addr = match self.mode(index) {
+            0 => self.param(index),
+            2 => self.relative_base.checked_add(self.param(index)).ok_or(VmError::Overflow { ip: self.ip })?,
+            _ => return Err(VmError::WriteToImmediate { ip: self.ip }),
+        };
+
+        if addr < 0 {
+            return Err(VmError::NegativeAddress { ip: self.ip, addr });
+        }
+
+        Ok(addr as usize)
+    }
+
+    fn load(&self, index: usize) -> Result<isize, VmError> {
+        match self.mode(index) {
+            1 => Ok(self.param(index)),
+            _ => Ok(self.read(self.address(index)?)),
+        }
+    }
+
+    fn store(&mut self, index: usize, value: isize) -> Result<(), Stop> {
+        let addr = self.address(index)?;
+
+        if addr >= MEMORY_LIMIT {
+            return Err(Stop::OutOfBounds);
+        }
+
+        if addr >= self.mem.len() {
+            self.mem.resize(addr + 1, 0);
+        }
+
+        self.mem[addr] = value;
+
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<(), Stop> {
+        let ip = self.ip;
+        let code = self.read(ip);
+
+        if code < 0 || !matches!(code % 100, 1..=9 | 99) {
+            return Err(Stop::Error(VmError::InvalidOpcode { ip, code }));
+        }
+
+        let mut modes = code / 100;
+
+        while modes > 0 {
+            if modes % 10 > 2 {
+                return Err(Stop::Error(VmError::InvalidMode { ip, mode: modes % 10 }));
+            }
+
+            modes /= 10;
+        }
+
+        match code % 100 {
+            1 => {
+                let value = self.load(0)?.checked_add(self.load(1)?).ok_or(VmError::Overflow { ip })?;
+                self.store(2, value)?;
+                self.ip += 4;
+            },
+            2 => {
+                let value = self.load(0)?.checked_mul(self.load(1)?).ok_or(VmError::Overflow { ip })?;
+                self.store(2, value)?;
+                self.ip += 4;
+            },
+            3 => {
+                if self.inputs.is_empty() {
+                    return Err(Stop::Error(VmError::InputExhausted { ip }));
+                }
+
+                let value = self.inputs.remove(0);
+                self.store(0, value)?;
+                self.ip += 2;
+            },
+            4 => {
+                let value = self.load(0)?;
+                self.outputs.push(value);
+                self.ip += 2;
+            },
+            5 | 6 => {
+                let cond = self.load(0)?;
+                let target = self.load(1)?;
+                let jump = if code % 100 == 5 { cond != 0 } else { cond == 0 };
+
+                if !jump {
+                    self.ip += 3;
+                } else if target < 0 {
+                    return Err(Stop::Error(VmError::NegativeAddress { ip, addr: target }));
+                } else {
+                    self.ip = target as usize;
+                }
+            },
+            7 => {
+                let value = if self.load(0)? < self.load(1)? { 1 } else { 0 };
+                self.store(2, value)?;
+                self.ip += 4;
+            },
+            8 => {
+                let value = if self.load(0)? == self.load(1)? { 1 } else { 0 };
+                self.store(2, value)?;
+                self.ip += 4;
+            },
+            9 => {
+                self.relative_base = self.relative_base.checked_add(self.load(0)?).ok_or(VmError::Overflow { ip })?;
+                self.ip += 2;
+            },
+            _ => return Err(Stop::Halt),
+        }
+
+        Ok(())
+    }
+}
+
+// Runs the reference interpreter, None when the case writes beyond
+// MEMORY_LIMIT
+pub fn reference(case: &Case, step_limit: usize) -> Option<Report> {
+    let mut reference = Reference {
+        mem: case.program.clone(),
+        inputs: case.inputs.clone(),
+        outputs: Vec::new(),
+        ip: 0,
+        relative_base: 0,
+    };
+
+    let mut outcome = Outcome::StepLimit;
+
+    for _ in 0..step_limit {
+        match reference.step() {
+            Ok(()) => {},
+            Err(Stop::Halt) => {
+                outcome = Outcome::Halted;
+                break;
+            },
+            Err(Stop::Error(err)) => {
+                outcome = Outcome::Error(err);
+                break;
+            },
+            Err(Stop::OutOfBounds) => return None,
+        }
+    }
+
+    let Reference { mem, outputs, ip, relative_base, .. } = reference;
+
+    Some(report(outcome, outputs, ip, relative_base, mem))
+}
+
+// Runs the VM under test, None when the case writes beyond MEMORY_LIMIT
+// like the reference
+pub fn run_vm(case: &Case, step_limit: usize) -> Option<Report> {
+    let mut vm = VM::new(case.program.clone());
+    vm.set_memory_limit(Some(MEMORY_LIMIT));
+
+    for &input in &case.inputs {
+        vm.add_input(input);
+    }
+
+    let mut outcome = Outcome::StepLimit;
+
+    for _ in 0..step_limit {
+        match vm.step() {
+            Ok(op_code) if op_code.is_halt() => {
+                outcome = Outcome::Halted;
+                break;
+            },
+            Ok(_) => {},
+            Err(VmError::LimitExceeded { limit: Limit::Memory, .. }) => return None,
+            Err(err) => {
+                outcome = Outcome::Error(err);
+                break;
+            },
+        }
+    }
+
+    let snapshot = vm.snapshot();
+    let mut mem = Vec::new();
+
+    for (start, cells) in snapshot.segments() {
+        let end = start + cells.len();
+
+        if mem.len() < end {
+            mem.resize(end, 0);
+        }
+
+        mem[*start..end].copy_from_slice(cells);
+    }
+
+    Some(report(outcome, vm.outputs().to_vec(), vm.ip(), vm.relative_base(), mem))
+}
+
+fn report(outcome: Outcome, outputs: Vec<isize>, ip: usize, relative_base: isize, mut mem: Vec<isize>) -> Report {
+    while mem.last() == Some(&0) {
+        mem.pop();
+    }
+
+    Report { outcome, outputs, ip, relative_base, mem }
+}
+
+// Compares the VM with the reference, cases the reference can't bound pass
+pub fn check(case: &Case, step_limit: usize) -> Result<(), Box<Mismatch>> {
+    let expected = match reference(case, step_limit) {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+
+    let actual = run_vm(case, step_limit);
+
+    if actual.as_ref() == Some(&expected) {
+        return Ok(());
+    }
+
+    Err(Box::new(Mismatch { case: case.clone(), expected, actual }))
+}
+
+// Greedily applies the first simplification that still fails until none
+// does. Every candidate is shorter or closer to zero, so this terminates.
+pub fn shrink(case: &Case, mut fails: impl FnMut(&Case) -> bool) -> Case {
+    let mut case = case.clone();
+
+    while let Some(smaller) = candidates(&case).into_iter().find(|candidate| fails(candidate)) {
+        case = smaller;
+    }
+
+    case
+}
+
+fn candidates(case: &Case) -> Vec<Case> {
+    let mut candidates = Vec::new();
+
+    for index in (0..case.inputs.len()).rev() {
+        let mut candidate = case.clone();
+        candidate.inputs.remove(index);
+        candidates.push(candidate);
+    }
+
+    // Runs of cells, longest first, since a single cell rarely removes a
+    // whole instruction
+    for size in (1..=case.program.len()).rev() {
+        for start in (0..=case.program.len() - size).rev() {
+            let mut candidate = case.clone();
+            candidate.program.drain(start..start + size);
+            candidates.push(candidate);
+        }
+    }
+
+    for (index, &value) in case.program.iter().enumerate() {
+        for simpler in simpler_values(value) {
+            let mut candidate = case.clone();
+            candidate.program[index] = simpler;
+            candidates.push(candidate);
+        }
+    }
+
+    for (index, &value) in case.inputs.iter().enumerate() {
+        for simpler in simpler_values(value) {
+            let mut candidate = case.clone();
+            candidate.inputs[index] = simpler;
+            candidates.push(candidate);
+        }
+    }
+
+    candidates
+}
+
+fn simpler_values(value: isize) -> Vec<isize> {
+    let mut values = vec![0, value / 2];
+
+    if value < 0 && value != isize::MIN {
+        values.push(-value);
+    }
+
+    // Halting early often keeps the failure with less code
+    if value.abs() > 99 {
+        values.push(99);
+    }
+
+    values.retain(|&simpler| simpler != value);
+    values
+}
+
+// Checks `count` generated cases, returning the first mismatch shrunk
+pub fn fuzz(seed: u64, count: usize, step_limit: usize) -> Result<(), Box<Mismatch>> {
+    let mut rng = Rng::new(seed);
+
+    for _ in 0..count {
+        let case = generate(&mut rng);
+
+        if check(&case, step_limit).is_err() {
+            let case = shrink(&case, |case| check(case, step_limit).is_err());
+            return check(&case, step_limit);
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "  outcome: {:?}", self.outcome)?;
+        writeln!(f, "  outputs: {:?}", self.outputs)?;
+        writeln!(f, "  ip: {}, relative base: {}", self.ip, self.relative_base)?;
+        write!(f, "  memory: {:?}", self.mem)
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let program = self.case.program.iter().map(isize::to_string).collect::<Vec<_>>();
+
+        writeln!(f, "program: {}", program.join(","))?;
+        writeln!(f, "inputs: {:?}", self.case.inputs)?;
+        writeln!(f, "reference:\n{}", self.expected)?;
+        match &self.actual {
+            Some(actual) => write!(f, "vm:\n{}", actual),
+            None => write!(f, "vm:\nwrote beyond the memory limit"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rng_is_reproducible() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+
+        assert_ne!(Rng::new(7).next_u64(), Rng::new(8).next_u64());
+        assert!((0..1000).all(|_| a.range(-3, 3).abs() <= 3));
+    }
+
+    #[test]
+    fn reference_agrees() {
+        let case = Case { program: vec![3, 9, 1002, 9, 3, 9, 204, 9, 99, 0], inputs: vec![5] };
+        let report = reference(&case, 100).unwrap();
+
+        assert_eq!(report.outcome, Outcome::Halted);
+        assert_eq!(report.outputs, [15]);
+        assert_eq!(Some(report), run_vm(&case, 100));
+
+        let case = Case { program: vec![1101, 1, 1, 1 << 20, 99], inputs: vec![] };
+        assert_eq!(reference(&case, 100), None);
+        assert_eq!(run_vm(&case, 100), None);
+    }
+
+    #[test]
+    fn vm_matches_reference() {
+        // Rare cases like relative base overflow take a few thousand cases
+        // to show up, so check several seeds
+        for seed in 0..8 {
+            if let Err(mismatch) = fuzz(seed, 5000, 200) {
+                panic!("seed {}: {}", seed, mismatch);
+            }
+        }
+    }
+
+    #[test]
+    fn shrinks_to_minimal_case() {
+        // Pretend that producing any output is a bug
+        let fails = |case: &Case| reference(case, 100).is_some_and(|report| !report.outputs.is_empty());
+
+        let case = Case {
+            program: vec![3, 20, 1002, 20, 7, 20, 1101, 1, 2, 21, 4, 20, 4, 21, 99],
+            inputs: vec![12, 3],
+        };
+        assert!(fails(&case));
+
+        // A lone `out [0]` prints itself and runs into an invalid opcode
+        assert_eq!(shrink(&case, fails), Case { program: vec![4], inputs: vec![] });
+    }
+}
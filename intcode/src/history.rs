@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+
+use crate::VM;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Undo {
+    pub(crate) ip: usize,
+    pub(crate) relative_base: isize,
+    // Address and previous value of the cell the instruction overwrote
+    pub(crate) write: Option<(usize, isize)>,
+    pub(crate) input: Option<isize>,
+    pub(crate) output: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct History {
+    limit: usize,
+    undo: VecDeque<Undo>,
+}
+
+impl History {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    pub(crate) fn push(&mut self, undo: Undo) {
+        if self.undo.len() == self.limit {
+            self.undo.pop_front();
+        }
+
+        self.undo.push_back(undo);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.undo.clear();
+    }
+}
+
+impl<'a, Context> VM<'a, Context> {
+    // Keeps enough state to undo the last `limit` instructions, 0 disables it
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.limit = limit;
+
+        while self.history.undo.len() > limit {
+            self.history.undo.pop_front();
+        }
+    }
+
+    pub fn history_limit(&self) -> usize {
+        self.history.limit
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.undo.len()
+    }
+
+    // Undoes the last executed instruction, returning false once the history
+    // is exhausted. Consumed inputs go back to the front of the input queue.
+    // Output handlers and channels have already seen their values, only the
+    // recorded outputs are rolled back.
+    pub fn step_back(&mut self) -> bool {
+        let undo = match self.history.undo.pop_back() {
+            Some(undo) => undo,
+            None => return false,
+        };
+
+        if let Some((addr, old)) = undo.write {
+            self.mem.write(addr, old);
+        }
+
+        if let Some(input) = undo.input {
+            self.input_queue.push_front(input);
+        }
+
+        if undo.output {
+            self.outputs.pop();
+        }
+
+        self.ip = undo.ip;
+        self.relative_base = undo.relative_base;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::VM;
+    use crate::asm::assemble;
+
+    // Reads a value, doubles it into a fresh relative frame and prints it
+    const PROGRAM: &str = "
+            in [value]
+            arb #20
+            mul [value], #2, rel[+0]
+            out rel[+0]
+            hlt
+        value: .data 0
+    ";
+
+    #[test]
+    fn undoes_every_effect() {
+        let mut vm = VM::new(assemble(PROGRAM).unwrap());
+        vm.set_history_limit(100);
+        vm.queue_input(21);
+        vm.run().unwrap();
+
+        assert_eq!(vm.outputs(), [42]);
+        assert_eq!(vm.history_len(), 5);
+
+        // hlt and out
+        assert!(vm.step_back());
+        assert!(vm.step_back());
+        assert_eq!(vm.outputs(), []);
+        assert_eq!(vm.read(20), 42);
+
+        assert!(vm.step_back());
+        assert_eq!(vm.read(20), 0);
+        assert_eq!(vm.ip(), 4);
+
+        assert!(vm.step_back());
+        assert_eq!(vm.relative_base(), 0);
+
+        assert!(vm.step_back());
+        assert_eq!(vm.read(11), 0);
+        assert_eq!(vm.ip(), 0);
+        assert!(!vm.step_back());
+
+        // The input is consumed again on the way forward
+        vm.run().unwrap();
+        assert_eq!(vm.outputs(), [42]);
+    }
+
+    #[test]
+    fn history_limit() {
+        let mut vm = VM::new(assemble(PROGRAM).unwrap());
+        vm.set_history_limit(2);
+        vm.queue_input(1);
+        vm.run().unwrap();
+
+        assert_eq!(vm.history_len(), 2);
+        assert!(vm.step_back());
+        assert!(vm.step_back());
+        assert!(!vm.step_back());
+        assert_eq!(vm.ip(), 8);
+
+        vm.set_history_limit(0);
+        vm.step().unwrap();
+        assert_eq!(vm.history_len(), 0);
+    }
+}
@@ -1,7 +1,44 @@
-use std::sync::mpsc::{channel, Sender, Receiver};
-
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
 use std::collections::VecDeque;
 
+use trace::{TraceEntry, MemoryWrite};
+use history::{History, Undo};
+use watchdog::Watchdog;
+
+mod error;
+pub mod disasm;
+pub mod asm;
+pub mod debugger;
+mod snapshot;
+mod save;
+pub mod memory;
+pub mod fast;
+pub mod aio;
+pub mod network;
+pub mod pipeline;
+pub mod ascii;
+pub mod adventure;
+mod profile;
+mod history;
+mod observer;
+mod watchdog;
+pub mod analysis;
+pub mod symbolic;
+pub mod trace;
+pub mod aot;
+pub mod fuzz;
+
+pub use error::{VmError, Limit, DecodeError, AsmError, SaveError, TraceError, SymbolicError, AdventureError};
+pub use snapshot::Snapshot;
+pub use memory::{Memory, DenseMemory, PagedMemory};
+pub use fast::FastVM;
+pub use profile::Profile;
+pub use trace::Trace;
+pub use observer::Observer;
+
+type InputProvider<'a, Context> = Box<dyn FnMut(&mut Context) -> isize + Send + 'a>;
+type OutputHandler<'a, Context> = Box<dyn FnMut(&mut Context, isize) + Send + 'a>;
+
 pub fn parse(input: &str) -> Vec<isize> {
     input
     .trim()
@@ -11,44 +48,68 @@ pub fn parse(input: &str) -> Vec<isize> {
 }
 
 pub struct VM<'a, Context = ()> {
-    mem: Vec<isize>,
+    mem: Box<dyn Memory + 'a>,
     ip: usize,
     relative_base: isize,
     input_queue: VecDeque<isize>,
-    input_tx: Sender<isize>,
+    // Moved out once the sender is handed out, so the channel closes when
+    // the last outside sender is dropped
+    input_tx: Option<Sender<isize>>,
     input_rx: Receiver<isize>,
     output_tx: Option<Sender<isize>>,
     outputs: Vec<isize>,
-    input_provider: Option<Box<dyn FnMut(&mut Context) -> isize + Send + 'a>>,
-    on_output: Option<Box<dyn FnMut(&mut Context, isize) + Send + 'a>>,
+    input_provider: Option<InputProvider<'a, Context>>,
+    on_output: Option<OutputHandler<'a, Context>>,
     debug: bool,
+    overflow_policy: OverflowPolicy,
     did_run: bool,
+    profile: Option<Box<Profile>>,
+    trace: Option<Box<Trace>>,
+    history: History,
+    watchdog: Watchdog,
     context: Context,
 }
 
-impl VM<'_, ()> {
+impl<'a> VM<'a, ()> {
     pub fn new(mem: impl Into<Vec<isize>>) -> Self {
         Self::with_context(mem, ())
     }
+
+    pub fn with_memory(memory: impl Memory + 'a) -> Self {
+        Self::with_memory_and_context(memory, ())
+    }
 }
 
 impl<'a, Context> VM<'a, Context> {
     pub fn with_context(mem: impl Into<Vec<isize>>, context: Context) -> Self {
+        Self::with_memory_and_context(DenseMemory::new(mem), context)
+    }
+
+    pub fn with_memory_and_context(memory: impl Memory + 'a, context: Context) -> Self {
+        Self::with_boxed_memory(Box::new(memory), context)
+    }
+
+    fn with_boxed_memory(mem: Box<dyn Memory + 'a>, context: Context) -> Self {
         let (input_tx, input_rx) = channel();
 
         Self {
-            mem: mem.into(),
+            mem,
             ip: 0,
             relative_base: 0,
             input_queue: VecDeque::new(),
-            input_tx,
+            input_tx: Some(input_tx),
             input_rx,
             output_tx: None,
             outputs: vec![],
             input_provider: None,
             on_output: None,
             debug: false,
+            overflow_policy: OverflowPolicy::default(),
             did_run: false,
+            profile: None,
+            trace: None,
+            history: History::default(),
+            watchdog: Watchdog::default(),
             context,
         }
     }
@@ -62,7 +123,14 @@ impl<'a, Context> VM<'a, Context> {
     }
 
     pub fn add_input(&mut self, value: isize) {
-        self.input_tx.send(value).ok();
+        match &self.input_tx {
+            Some(input_tx) => { input_tx.send(value).ok(); },
+            // Values already sent on the shared channel come first
+            None => {
+                self.drain_input_channel();
+                self.input_queue.push_back(value);
+            },
+        }
     }
 
     pub fn queue_input(&mut self, value: isize) {
@@ -73,16 +141,23 @@ impl<'a, Context> VM<'a, Context> {
         self.output_tx = Some(output_tx);
     }
 
-    pub fn input(&self) -> Sender<isize> {
-        self.input_tx.clone()
+    // Hands out the sending side of the input channel. Reads block until a
+    // value arrives and fail once every clone of it has been dropped. Can
+    // only be called once, clone the sender to feed the VM from several places.
+    pub fn input(&mut self) -> Sender<isize> {
+        self.input_tx.take().expect("the input sender was already handed out")
     }
 
     pub fn set_debug(&mut self, state: bool) {
         self.debug = state;
     }
 
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
     pub fn read(&self, addr: usize) -> isize {
-        let value = self.mem.get(addr).copied().unwrap_or(0);
+        let value = self.mem.read(addr);
 
         if self.debug {
             println!("read({}) -> {}", addr, value);
@@ -96,8 +171,7 @@ impl<'a, Context> VM<'a, Context> {
             println!("write({}) -> {}", addr, value);
         }
 
-        self.ensure_mem_size(addr + 1);
-        self.mem[addr] = value;
+        self.mem.write(addr, value);
     }
 
     pub fn outputs(&self) -> &[isize] {
@@ -112,11 +186,27 @@ impl<'a, Context> VM<'a, Context> {
         self.read(self.ip)
     }
 
-    pub fn next_op_code(&self) -> OpCode {
-        OpCode::parse(self.code())
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    pub fn next_op_code(&self) -> Result<OpCode, VmError> {
+        OpCode::parse(self.code()).map_err(|err| err.at(self.ip))
+    }
+
+    fn check_addr(&self, addr: isize) -> Result<usize, VmError> {
+        if addr < 0 {
+            return Err(VmError::NegativeAddress { ip: self.ip, addr });
+        }
+
+        Ok(addr as usize)
     }
 
-    fn read_arg(&self, index: usize, modes: &[Mode]) -> isize {
+    fn read_arg(&self, index: usize, modes: &[Mode]) -> Result<isize, VmError> {
         assert!(index > 0);
 
         let addr = match modes.get(index - 1).unwrap_or(&Mode::Position) {
@@ -124,58 +214,87 @@ impl<'a, Context> VM<'a, Context> {
             Mode::Immediate => (self.ip + index) as isize,
             Mode::Relative => {
                 let offset = self.read(self.ip + index);
-                self.relative_base + offset
+                self.overflow_policy.add(self.ip, self.relative_base, offset)?
             }
         };
 
-        self.read(addr as usize)
+        let addr = self.check_addr(addr)?;
+
+        Ok(self.read(addr))
     }
 
-    fn write_arg(&mut self, index: usize, value: isize, modes: &[Mode]) {
+    fn write_arg(&mut self, index: usize, value: isize, modes: &[Mode]) -> Result<(), VmError> {
         assert!(index > 0);
 
         let arg = self.read(self.ip + index);
 
         let addr = match modes.get(index -1).unwrap_or(&Mode::Position) {
-            Mode::Position | Mode::Immediate => arg,
-            Mode::Relative => self.relative_base + arg,
+            Mode::Position => arg,
+            Mode::Immediate => return Err(VmError::WriteToImmediate { ip: self.ip }),
+            Mode::Relative => self.overflow_policy.add(self.ip, self.relative_base, arg)?,
         };
 
-        self.write(addr as usize, value);
+        let addr = self.check_addr(addr)?;
+        self.check_memory(addr)?;
+        self.write(addr, value);
+
+        Ok(())
     }
 
-    pub fn run(&mut self) {
-        while !self.step().is_halt() {}
+    pub fn run(&mut self) -> Result<(), VmError> {
+        self.reset_time_limit();
+
+        while !self.step()?.is_halt() {}
+
+        Ok(())
     }
 
-    pub fn run_tracing(&mut self, mut tracer: impl FnMut(&mut Self, OpCode, &OpCode)) {
-        let mut previous_op_code = OpCode {
-            op: Op::Halt,
-            modes: vec![],
-        };
+    pub fn run_until_blocked(&mut self) -> Result<RunState, VmError> {
+        self.did_run = true;
+        self.reset_time_limit();
 
         loop {
-            let next_op_code = self.next_op_code();
+            let next_op_code = self.next_op_code()?;
+
+            match next_op_code.op {
+                Op::Halt => return Ok(RunState::Halted),
+                Op::ReadInput if !self.has_input() => return Ok(RunState::NeedsInput),
+                _ => {},
+            }
 
-            tracer(self, previous_op_code, &next_op_code);
-            self.execute(&next_op_code);
+            self.execute(&next_op_code)?;
 
-            if next_op_code.is_halt() {
-                return;
+            if next_op_code.op == Op::WriteOutput {
+                let output = self.outputs.last().copied().expect("missing output");
+                return Ok(RunState::Output(output));
             }
+        }
+    }
 
-            previous_op_code = next_op_code;
+    pub(crate) fn has_input(&mut self) -> bool {
+        if !self.input_queue.is_empty() || self.input_provider.is_some() {
+            return true;
+        }
+
+        // The queue is empty, so moving the value there keeps the input order
+        match self.input_rx.try_recv() {
+            Ok(value) => {
+                self.input_queue.push_back(value);
+                true
+            },
+            Err(_) => false,
         }
     }
 
-    pub fn step(&mut self) -> OpCode {
-        let next_op_code = self.next_op_code();
-        self.execute(&next_op_code);
-        next_op_code
+    pub fn step(&mut self) -> Result<OpCode, VmError> {
+        let next_op_code = self.next_op_code()?;
+        self.execute(&next_op_code)?;
+        Ok(next_op_code)
     }
 
-    pub fn execute(&mut self, op_code: &OpCode) {
+    pub fn execute(&mut self, op_code: &OpCode) -> Result<(), VmError> {
         self.did_run = true;
+        self.check_limits()?;
 
         let modes = &op_code.modes;
 
@@ -183,55 +302,129 @@ impl<'a, Context> VM<'a, Context> {
             println!("{:?}", op_code);
         }
 
+        let access = match self.profile.is_some() || self.trace.is_some() || self.history.is_enabled() {
+            true => Some(self.access(op_code)),
+            false => None,
+        };
+        let (ip, relative_base) = (self.ip, self.relative_base);
+        let old_value = access.as_ref().and_then(|access| access.write).map(|addr| self.mem.read(addr));
+        let output_count = self.outputs.len();
+
         match op_code.op {
-            Op::Add => self.op_add(modes),
-            Op::Mul => self.op_mul(modes),
-            Op::ReadInput => self.op_read_input(modes),
-            Op::WriteOutput => self.op_write_output(modes),
-            Op::JumpIfTrue => self.op_jump_if_true(modes),
-            Op::JumpIfFalse => self.op_jump_if_false(modes),
-            Op::LessThan => self.op_less_than(modes),
-            Op::Equals => self.op_equals(modes),
-            Op::AdjustRelativeBase => self.op_adjust_relative_base(modes),
+            Op::Add => self.op_add(modes)?,
+            Op::Mul => self.op_mul(modes)?,
+            Op::ReadInput => self.op_read_input(modes)?,
+            Op::WriteOutput => self.op_write_output(modes)?,
+            Op::JumpIfTrue => self.op_jump_if_true(modes)?,
+            Op::JumpIfFalse => self.op_jump_if_false(modes)?,
+            Op::LessThan => self.op_less_than(modes)?,
+            Op::Equals => self.op_equals(modes)?,
+            Op::AdjustRelativeBase => self.op_adjust_relative_base(modes)?,
             Op::Halt => {},
         }
-    }
 
-    fn ensure_mem_size(&mut self, size: usize) {
-        while self.mem.len() < size {
-            self.mem.push(0);
+        if let Some(access) = access {
+            let written = access.write.map(|addr| self.mem.read(addr));
+            let input = if op_code.op == Op::ReadInput { written } else { None };
+            let output = self.outputs.get(output_count).copied();
+
+            if self.history.is_enabled() {
+                self.history.push(Undo {
+                    ip,
+                    relative_base,
+                    write: access.write.zip(old_value),
+                    input,
+                    output: output.is_some(),
+                });
+            }
+
+            if let Some(profile) = &mut self.profile {
+                profile.record(ip, op_code, &access, self.ip, written);
+            }
+
+            if let Some(trace) = &mut self.trace {
+                trace.record(TraceEntry {
+                    ip,
+                    code: access.code,
+                    args: access.args,
+                    addrs: access.addrs,
+                    relative_base,
+                    next_relative_base: self.relative_base,
+                    write: access.write.map(|addr| MemoryWrite {
+                        addr,
+                        old: old_value.expect("old value"),
+                        new: written.expect("written value"),
+                    }),
+                    input,
+                    output,
+                    next_ip: self.ip,
+                });
+            }
+        }
+
+        if op_code.op == Op::ReadInput {
+            self.watchdog.forget_states();
         }
+
+        if self.watchdog.detects_loops() && op_code.op.is_jump() && self.ip <= ip {
+            self.check_loop(ip)?;
+        }
+
+        Ok(())
     }
 
-    fn op_add(&mut self, modes: &[Mode]) {
-        let a = self.read_arg(1, modes);
-        let b = self.read_arg(2, modes);
-        self.write_arg(3, a + b, modes);
+    fn op_add(&mut self, modes: &[Mode]) -> Result<(), VmError> {
+        let a = self.read_arg(1, modes)?;
+        let b = self.read_arg(2, modes)?;
+        let value = self.overflow_policy.add(self.ip, a, b)?;
+        self.write_arg(3, value, modes)?;
         self.ip += 4;
+
+        Ok(())
     }
 
-    fn op_mul(&mut self, modes: &[Mode]) {
-        let a = self.read_arg(1, modes);
-        let b = self.read_arg(2, modes);
-        self.write_arg(3, a * b, modes);
+    fn op_mul(&mut self, modes: &[Mode]) -> Result<(), VmError> {
+        let a = self.read_arg(1, modes)?;
+        let b = self.read_arg(2, modes)?;
+        let value = self.overflow_policy.mul(self.ip, a, b)?;
+        self.write_arg(3, value, modes)?;
         self.ip += 4;
+
+        Ok(())
     }
 
-    fn op_read_input(&mut self, modes: &[Mode]) {
+    fn op_read_input(&mut self, modes: &[Mode]) -> Result<(), VmError> {
         let queued_input = self.input_queue.pop_front();
 
         let value = match (queued_input, &mut self.input_provider) {
             (Some(queued_input), _) => queued_input,
             (_, Some(input_provider)) => input_provider(&mut self.context),
-            _ => self.input_rx.recv().expect("failed to read value"),
+            _ => self.recv_input()?,
         };
 
-        self.write_arg(1, value, modes);
+        self.write_arg(1, value, modes)?;
         self.ip += 2;
+
+        Ok(())
     }
 
-    fn op_write_output(&mut self, modes: &[Mode]) {
-        let value = self.read_arg(1, modes);
+    fn recv_input(&self) -> Result<isize, VmError> {
+        let exhausted = VmError::InputExhausted { ip: self.ip };
+
+        // Without a shared sender nobody else can feed the channel,
+        // so waiting on it would block forever.
+        if self.input_tx.is_none() {
+            return self.input_rx.recv().map_err(|_| exhausted);
+        }
+
+        match self.input_rx.try_recv() {
+            Ok(value) => Ok(value),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => Err(exhausted),
+        }
+    }
+
+    fn op_write_output(&mut self, modes: &[Mode]) -> Result<(), VmError> {
+        let value = self.read_arg(1, modes)?;
 
         if self.debug {
             println!("Output: {}", value);
@@ -247,68 +440,80 @@ impl<'a, Context> VM<'a, Context> {
 
         self.outputs.push(value);
         self.ip += 2;
+
+        Ok(())
     }
 
-    fn op_jump_if_true(&mut self, modes: &[Mode]) {
-        let cond = self.read_arg(1, modes);
-        let ip = self.read_arg(2, modes);
+    fn op_jump_if_true(&mut self, modes: &[Mode]) -> Result<(), VmError> {
+        let cond = self.read_arg(1, modes)?;
+        let ip = self.read_arg(2, modes)?;
 
         if cond != 0 {
-            self.ip = ip as usize;
+            self.ip = self.check_addr(ip)?;
         } else {
             self.ip += 3;
         }
+
+        Ok(())
     }
 
-    fn op_jump_if_false(&mut self, modes: &[Mode]) {
-        let cond = self.read_arg(1, modes);
-        let ip = self.read_arg(2, modes);
+    fn op_jump_if_false(&mut self, modes: &[Mode]) -> Result<(), VmError> {
+        let cond = self.read_arg(1, modes)?;
+        let ip = self.read_arg(2, modes)?;
 
         if cond == 0 {
-            self.ip = ip as usize;
+            self.ip = self.check_addr(ip)?;
         } else {
             self.ip += 3;
         }
+
+        Ok(())
     }
 
-    fn op_less_than(&mut self, modes: &[Mode]) {
-        let a = self.read_arg(1, modes);
-        let b = self.read_arg(2, modes);
+    fn op_less_than(&mut self, modes: &[Mode]) -> Result<(), VmError> {
+        let a = self.read_arg(1, modes)?;
+        let b = self.read_arg(2, modes)?;
         let value = (a < b) as isize;
 
-        self.write_arg(3, value, modes);
+        self.write_arg(3, value, modes)?;
         self.ip += 4;
+
+        Ok(())
     }
 
-    fn op_equals(&mut self, modes: &[Mode]) {
-        let a = self.read_arg(1, modes);
-        let b = self.read_arg(2, modes);
+    fn op_equals(&mut self, modes: &[Mode]) -> Result<(), VmError> {
+        let a = self.read_arg(1, modes)?;
+        let b = self.read_arg(2, modes)?;
         let value = (a == b) as isize;
 
-        self.write_arg(3, value, modes);
+        self.write_arg(3, value, modes)?;
         self.ip += 4;
+
+        Ok(())
     }
 
-    fn op_adjust_relative_base(&mut self, modes: &[Mode]) {
-        let adjustment = self.read_arg(1, modes);
-        self.relative_base += adjustment;
+    fn op_adjust_relative_base(&mut self, modes: &[Mode]) -> Result<(), VmError> {
+        let adjustment = self.read_arg(1, modes)?;
+        self.relative_base = self.overflow_policy.add(self.ip, self.relative_base, adjustment)?;
 
         if self.debug {
             println!("new relative base: {}", self.relative_base);
         }
 
         self.ip += 2;
+
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OpCode {
     op: Op,
     modes: Vec<Mode>,
 }
 
 impl OpCode {
-    fn parse(mut code: isize) -> Self {
+    pub fn parse(mut code: isize) -> Result<Self, DecodeError> {
         let op = match code % 100 {
             1 => Op::Add,
             2 => Op::Mul,
@@ -320,7 +525,7 @@ impl OpCode {
             8 => Op::Equals,
             9 => Op::AdjustRelativeBase,
             99 => Op::Halt,
-            code => unreachable!("code: {}", code),
+            _ => return Err(DecodeError::InvalidOpcode(code)),
         };
         code /= 100;
 
@@ -331,16 +536,16 @@ impl OpCode {
                 0 => Mode::Position,
                 1 => Mode::Immediate,
                 2 => Mode::Relative,
-                mode => unreachable!("mode: {}", mode),
+                mode => return Err(DecodeError::InvalidMode(mode)),
             };
 
             modes.push(mode);
             code /= 10;
         }
 
-        OpCode {
+        Ok(OpCode {
             op, modes
-        }
+        })
     }
 
     pub fn op(&self) -> Op {
@@ -351,12 +556,52 @@ impl OpCode {
         &self.modes
     }
 
+    pub fn mode(&self, index: usize) -> Mode {
+        self.modes.get(index).copied().unwrap_or(Mode::Position)
+    }
+
     pub fn is_halt(&self) -> bool {
         self.op == Op::Halt
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum RunState {
+    Halted,
+    NeedsInput,
+    Output(isize),
+}
+
+// How Add, Mul and relative base adjustments handle results that don't
+// fit an isize. The default fails with `VmError::Overflow` in every build
+// profile, where release builds used to wrap silently; `Wrapping` restores
+// that. Cells are always isize, a big-integer word type would mean making
+// every backend generic and isn't supported.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    Checked,
+    Wrapping,
+}
+
+impl OverflowPolicy {
+    pub(crate) fn add(self, ip: usize, a: isize, b: isize) -> Result<isize, VmError> {
+        self.apply(ip, a.overflowing_add(b))
+    }
+
+    pub(crate) fn mul(self, ip: usize, a: isize, b: isize) -> Result<isize, VmError> {
+        self.apply(ip, a.overflowing_mul(b))
+    }
+
+    fn apply(self, ip: usize, (value, overflowed): (isize, bool)) -> Result<isize, VmError> {
+        match self {
+            OverflowPolicy::Checked if overflowed => Err(VmError::Overflow { ip }),
+            _ => Ok(value),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 pub enum Op {
     Add,
     Mul,
@@ -370,6 +615,73 @@ pub enum Op {
     Halt,
 }
 
+impl Op {
+    pub fn arg_count(self) -> usize {
+        match self {
+            Op::Add | Op::Mul | Op::LessThan | Op::Equals => 3,
+            Op::JumpIfTrue | Op::JumpIfFalse => 2,
+            Op::ReadInput | Op::WriteOutput | Op::AdjustRelativeBase => 1,
+            Op::Halt => 0,
+        }
+    }
+
+    pub fn writes(self) -> bool {
+        matches!(self, Op::Add | Op::Mul | Op::LessThan | Op::Equals | Op::ReadInput)
+    }
+
+    pub fn is_jump(self) -> bool {
+        self == Op::JumpIfTrue || self == Op::JumpIfFalse
+    }
+
+    pub fn code(self) -> isize {
+        match self {
+            Op::Add => 1,
+            Op::Mul => 2,
+            Op::ReadInput => 3,
+            Op::WriteOutput => 4,
+            Op::JumpIfTrue => 5,
+            Op::JumpIfFalse => 6,
+            Op::LessThan => 7,
+            Op::Equals => 8,
+            Op::AdjustRelativeBase => 9,
+            Op::Halt => 99,
+        }
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        let op = match mnemonic {
+            "add" => Op::Add,
+            "mul" => Op::Mul,
+            "in" | "read_input" => Op::ReadInput,
+            "out" | "write_output" => Op::WriteOutput,
+            "jt" | "jump_if_true" => Op::JumpIfTrue,
+            "jf" | "jump_if_false" => Op::JumpIfFalse,
+            "lt" | "less_than" => Op::LessThan,
+            "eq" | "equals" => Op::Equals,
+            "arb" | "adjust_relative_base" => Op::AdjustRelativeBase,
+            "hlt" | "halt" => Op::Halt,
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Op::Add => "add",
+            Op::Mul => "mul",
+            Op::ReadInput => "in",
+            Op::WriteOutput => "out",
+            Op::JumpIfTrue => "jt",
+            Op::JumpIfFalse => "jf",
+            Op::LessThan => "lt",
+            Op::Equals => "eq",
+            Op::AdjustRelativeBase => "arb",
+            Op::Halt => "hlt",
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Mode {
     Position,
@@ -377,6 +689,24 @@ pub enum Mode {
     Relative,
 }
 
+impl Mode {
+    pub fn code(self) -> isize {
+        match self {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+}
+
+impl<Context> Drop for VM<'_, Context> {
+    fn drop(&mut self) {
+        if !self.did_run {
+            eprintln!("WARNING: VM did not run");
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -384,13 +714,13 @@ mod tests {
     #[test]
     fn halt() {
         let mut vm = VM::new(vec![99]);
-        vm.run();
+        vm.run().unwrap();
     }
 
     #[test]
     fn add_immediate() {
         let mut vm = VM::new(vec![11_01, 2, 3, 0, 99]);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.read(0), 5);
     }
 
@@ -398,13 +728,39 @@ mod tests {
     fn read_input() {
         let mut vm = VM::new(vec![3, 0, 99]);
         vm.add_input(42);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.read(0), 42);
     }
 
     #[test]
+    fn shared_input_closes() {
+        let mut vm = VM::new(vec![3, 0, 4, 0, 3, 0, 99]);
+        vm.add_input(1);
+
+        let input = vm.input();
+        vm.add_input(2);
+
+        // add_input keeps working after sharing and keeps the input order
+        let feeder = std::thread::spawn(move || input.send(3).unwrap());
+        feeder.join().unwrap();
+
+        assert_eq!(vm.run(), Ok(()));
+        assert_eq!(vm.read(0), 2);
+
+        // Dropping the last sender ends the input once the channel is drained
+        let mut vm = VM::new(vec![3, 0, 4, 0, 3, 0, 99]);
+        let input = vm.input();
+        input.send(7).unwrap();
+        drop(input);
+
+        assert_eq!(vm.run(), Err(VmError::InputExhausted { ip: 4 }));
+        assert_eq!(vm.outputs(), [7]);
+    }
+
+    #[test]
+    #[allow(clippy::inconsistent_digit_grouping)]
     fn modes() {
-        let op_code = OpCode::parse(201_01);
+        let op_code = OpCode::parse(201_01).unwrap();
 
         assert_eq!(op_code.modes, [
             Mode::Immediate,
@@ -412,12 +768,74 @@ mod tests {
             Mode::Relative,
         ]);
     }
-}
 
-impl<Context> Drop for VM<'_, Context> {
-    fn drop(&mut self) {
-        if !self.did_run {
-            eprintln!("WARNING: VM did not run");
-        }
+    #[test]
+    fn run_until_blocked() {
+        let mut vm = VM::new(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]);
+
+        assert_eq!(vm.run_until_blocked(), Ok(RunState::NeedsInput));
+        assert_eq!(vm.ip(), 0);
+
+        vm.add_input(41);
+        assert_eq!(vm.run_until_blocked(), Ok(RunState::Output(42)));
+        assert_eq!(vm.run_until_blocked(), Ok(RunState::Halted));
+        assert_eq!(vm.run_until_blocked(), Ok(RunState::Halted));
+    }
+
+    #[test]
+    fn overflow_policy() {
+        let program = vec![1102, isize::MAX, 2, 7, 4, 7, 99, 0];
+
+        let mut vm = VM::new(program.clone());
+        assert_eq!(vm.run(), Err(VmError::Overflow { ip: 0 }));
+
+        let mut vm = VM::new(program);
+        vm.set_overflow_policy(OverflowPolicy::Wrapping);
+        vm.run().unwrap();
+        assert_eq!(vm.outputs(), [-2]);
+    }
+
+    #[test]
+    fn relative_base_overflow_policy() {
+        let program = vec![109, isize::MAX, 109, 1, 99];
+
+        let mut vm = VM::new(program.clone());
+        assert_eq!(vm.run(), Err(VmError::Overflow { ip: 2 }));
+
+        let mut vm = VM::new(program);
+        vm.set_overflow_policy(OverflowPolicy::Wrapping);
+        vm.run().unwrap();
+        assert_eq!(vm.relative_base(), isize::MIN);
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        let mut vm = VM::new(vec![1, 0, 0, 0, 42]);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode { ip: 4, code: 42 }));
+    }
+
+    #[test]
+    fn invalid_mode() {
+        let mut vm = VM::new(vec![301, 0, 0, 0, 99]);
+        assert_eq!(vm.run(), Err(VmError::InvalidMode { ip: 0, mode: 3 }));
+    }
+
+    #[test]
+    fn negative_address() {
+        let mut vm = VM::new(vec![1, -1, 0, 0, 99]);
+        assert_eq!(vm.run(), Err(VmError::NegativeAddress { ip: 0, addr: -1 }));
+    }
+
+    #[test]
+    fn write_to_immediate() {
+        let mut vm = VM::new(vec![11_101, 1, 1, 0, 99]);
+        assert_eq!(vm.run(), Err(VmError::WriteToImmediate { ip: 0 }));
+    }
+
+    #[test]
+    fn input_exhausted() {
+        let mut vm = VM::new(vec![3, 0, 3, 0, 99]);
+        vm.add_input(1);
+        assert_eq!(vm.run(), Err(VmError::InputExhausted { ip: 2 }));
     }
 }
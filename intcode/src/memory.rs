@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+pub trait Memory: Send {
+    fn read(&self, addr: usize) -> isize;
+    fn write(&mut self, addr: usize, value: isize);
+    fn segments(&self) -> Vec<(usize, &[isize])>;
+    fn clear(&mut self);
+    fn new_empty(&self) -> Box<dyn Memory>;
+
+    // Replaces the whole memory with `segments`, as returned by `segments`
+    fn load_segments(&mut self, segments: &[(usize, Vec<isize>)]) {
+        self.clear();
+
+        // Cells past the end of the address space can't be addressed anyway
+        for (addr, cells) in segments {
+            for (offset, &value) in cells.iter().enumerate() {
+                match addr.checked_add(offset) {
+                    Some(addr) => self.write(addr, value),
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DenseMemory {
+    cells: Vec<isize>,
+}
+
+impl DenseMemory {
+    pub fn new(cells: impl Into<Vec<isize>>) -> Self {
+        Self { cells: cells.into() }
+    }
+}
+
+impl Memory for DenseMemory {
+    fn read(&self, addr: usize) -> isize {
+        self.cells.get(addr).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, addr: usize, value: isize) {
+        if addr >= self.cells.len() {
+            self.cells.resize(addr + 1, 0);
+        }
+
+        self.cells[addr] = value;
+    }
+
+    fn segments(&self) -> Vec<(usize, &[isize])> {
+        vec![(0, &self.cells)]
+    }
+
+    fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    fn new_empty(&self) -> Box<dyn Memory> {
+        Box::new(DenseMemory::default())
+    }
+
+    // Reuses the allocation and copies whole segments
+    fn load_segments(&mut self, segments: &[(usize, Vec<isize>)]) {
+        // Segments running past the end of the address space are skipped
+        let fitting = || segments
+            .iter()
+            .filter_map(|(addr, cells)| Some((*addr, addr.checked_add(cells.len())?, cells)));
+        let len = fitting().map(|(_, end, _)| end).max().unwrap_or(0);
+
+        self.cells.clear();
+        self.cells.resize(len, 0);
+
+        for (addr, end, cells) in fitting() {
+            self.cells[addr..end].copy_from_slice(cells);
+        }
+    }
+}
+
+pub const PAGE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PagedMemory {
+    pages: HashMap<usize, Box<[isize]>>,
+}
+
+impl PagedMemory {
+    pub fn new(cells: &[isize]) -> Self {
+        let mut memory = Self::default();
+
+        for (addr, &value) in cells.iter().enumerate() {
+            memory.write(addr, value);
+        }
+
+        memory
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+impl Memory for PagedMemory {
+    fn read(&self, addr: usize) -> isize {
+        match self.pages.get(&(addr / PAGE_SIZE)) {
+            Some(page) => page[addr % PAGE_SIZE],
+            None => 0,
+        }
+    }
+
+    fn write(&mut self, addr: usize, value: isize) {
+        let index = addr / PAGE_SIZE;
+
+        // Untouched pages already read as zero
+        if value == 0 && !self.pages.contains_key(&index) {
+            return;
+        }
+
+        let page = self.pages
+            .entry(index)
+            .or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice());
+
+        page[addr % PAGE_SIZE] = value;
+    }
+
+    fn segments(&self) -> Vec<(usize, &[isize])> {
+        let mut segments = self.pages
+            .iter()
+            .map(|(&page, cells)| (page * PAGE_SIZE, &cells[..]))
+            .collect::<Vec<_>>();
+
+        segments.sort_by_key(|&(addr, _)| addr);
+        segments
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    fn new_empty(&self) -> Box<dyn Memory> {
+        Box::new(PagedMemory::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VM;
+
+    #[test]
+    fn dense_grows_on_write() {
+        let mut memory = DenseMemory::new(vec![1, 2]);
+        memory.write(5, 3);
+
+        assert_eq!(memory.segments(), [(0, &[1, 2, 0, 0, 0, 3][..])]);
+        assert_eq!(memory.read(100), 0);
+    }
+
+    #[test]
+    fn paged_allocates_only_touched_pages() {
+        let mut memory = PagedMemory::new(&[1, 2, 3]);
+        memory.write(1_000_000_000, 42);
+        memory.write(2_000_000_000, 0);
+
+        assert_eq!(memory.page_count(), 2);
+        assert_eq!(memory.read(1), 2);
+        assert_eq!(memory.read(1_000_000_000), 42);
+        assert_eq!(memory.read(1_000_000_001), 0);
+        assert_eq!(memory.read(2_000_000_000), 0);
+    }
+
+    #[test]
+    fn load_segments() {
+        let segments = [(0, vec![1, 2]), (4, vec![3])];
+
+        let mut dense = DenseMemory::new(vec![9; 10]);
+        dense.load_segments(&segments);
+        assert_eq!(dense.segments(), [(0, &[1, 2, 0, 0, 3][..])]);
+
+        let mut paged = PagedMemory::new(&[9; 10]);
+        paged.load_segments(&segments);
+        assert_eq!((paged.read(1), paged.read(4), paged.read(9)), (2, 3, 0));
+    }
+
+    #[test]
+    fn load_segments_past_the_address_space() {
+        let segments = [(0, vec![1]), (usize::MAX, vec![2, 3])];
+
+        let mut dense = DenseMemory::default();
+        dense.load_segments(&segments);
+        assert_eq!(dense.segments(), [(0, &[1][..])]);
+
+        let mut paged = PagedMemory::default();
+        paged.load_segments(&segments);
+        assert_eq!((paged.read(0), paged.read(usize::MAX)), (1, 2));
+    }
+
+    #[test]
+    fn vm_with_paged_memory() {
+        // add 2 + 3 and store the result far away, then print it
+        let program = [1101, 2, 3, 1_000_000_000, 4, 1_000_000_000, 99];
+        let mut vm = VM::with_memory(PagedMemory::new(&program));
+        vm.run().unwrap();
+
+        assert_eq!(vm.outputs(), [5]);
+        assert_eq!(vm.read(1_000_000_000), 5);
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+
+use crate::{VM, RunState, VmError};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub dest: isize,
+    pub x: isize,
+    pub y: isize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    // A packet addressed to something other than a machine, e.g. a NAT on 255
+    Packet(Packet),
+    // Every queue is empty and every machine just read -1
+    Idle,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    Continue,
+    Send(Packet),
+    Stop,
+}
+
+struct Machine<'a> {
+    vm: VM<'a>,
+    queue: VecDeque<(isize, isize)>,
+    partial: Vec<isize>,
+    halted: bool,
+}
+
+pub struct Network<'a> {
+    machines: Vec<Machine<'a>>,
+}
+
+impl<'a> Network<'a> {
+    pub fn new(program: &[isize], size: usize) -> Self {
+        let machines = (0..size)
+            .map(|address| {
+                let mut vm = VM::new(program);
+                vm.queue_input(address as isize);
+
+                Machine {
+                    vm,
+                    queue: VecDeque::new(),
+                    partial: Vec::with_capacity(3),
+                    halted: false,
+                }
+            })
+            .collect();
+
+        Self { machines }
+    }
+
+    pub fn size(&self) -> usize {
+        self.machines.len()
+    }
+
+    pub fn vm(&self, address: usize) -> &VM<'a> {
+        &self.machines[address].vm
+    }
+
+    pub fn queue_len(&self, address: usize) -> usize {
+        self.machines[address].queue.len()
+    }
+
+    // Returns the packet back if its destination is not a machine
+    pub fn send(&mut self, packet: Packet) -> Result<(), Packet> {
+        if packet.dest < 0 {
+            return Err(packet);
+        }
+
+        match self.machines.get_mut(packet.dest as usize) {
+            Some(machine) => {
+                machine.queue.push_back((packet.x, packet.y));
+                Ok(())
+            },
+            None => Err(packet),
+        }
+    }
+
+    pub fn run(&mut self, mut hook: impl FnMut(Event) -> Action) -> Result<(), VmError> {
+        loop {
+            let mut outgoing = Vec::new();
+            let mut idle = true;
+
+            for machine in &mut self.machines {
+                idle &= machine.run_slice(&mut outgoing)?;
+            }
+
+            idle &= outgoing.is_empty() && self.machines.iter().all(|machine| machine.queue.is_empty());
+
+            for packet in outgoing {
+                if let Err(packet) = self.send(packet) {
+                    if !self.handle(hook(Event::Packet(packet)))? {
+                        return Ok(());
+                    }
+                }
+            }
+
+            if self.machines.iter().all(|machine| machine.halted) {
+                return Ok(());
+            }
+
+            if idle && !self.handle(hook(Event::Idle))? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn handle(&mut self, action: Action) -> Result<bool, VmError> {
+        match action {
+            Action::Continue => Ok(true),
+            Action::Stop => Ok(false),
+            Action::Send(packet) => {
+                // Unroutable packets from a hook are dropped
+                self.send(packet).ok();
+                Ok(true)
+            },
+        }
+    }
+}
+
+impl Machine<'_> {
+    // Runs until the machine polls an empty queue a second time, returning
+    // whether it was idle. Whether a read gets a packet or -1 is decided when
+    // the machine reads, so packets sent meanwhile are never delayed.
+    fn run_slice(&mut self, outgoing: &mut Vec<Packet>) -> Result<bool, VmError> {
+        if self.halted {
+            return Ok(true);
+        }
+
+        let mut idle = true;
+        let mut polled_empty = false;
+
+        loop {
+            match self.vm.run_until_blocked()? {
+                RunState::Output(value) => {
+                    idle = false;
+                    self.partial.push(value);
+
+                    if let [dest, x, y] = self.partial[..] {
+                        outgoing.push(Packet { dest, x, y });
+                        self.partial.clear();
+                    }
+                },
+                RunState::NeedsInput => match self.queue.pop_front() {
+                    Some((x, y)) => {
+                        idle = false;
+                        self.vm.queue_input(x);
+                        self.vm.queue_input(y);
+                    },
+                    None if polled_empty => return Ok(idle),
+                    None => {
+                        polled_empty = true;
+                        self.vm.queue_input(-1);
+                    },
+                },
+                RunState::Halted => {
+                    self.halted = true;
+                    return Ok(idle);
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // Machine 0 sends (10, 20) to machine 1, every machine forwards
+    // received packets to 255 with its address added to y.
+    const PROGRAM: &str = "
+            in [addr]
+            jt [addr], #recv
+            out #1
+            out #10
+            out #20
+        recv:
+            in [x]
+            eq [x], #-1, [tmp]
+            jt [tmp], #recv
+            in [y]
+            out #255
+            out [x]
+            add [y], [addr], [y]
+            out [y]
+            jt #1, #recv
+        addr: .data 0
+        x: .data 0
+        y: .data 0
+        tmp: .data 0
+    ";
+
+    #[test]
+    fn routes_packets_and_detects_idle() {
+        let program = assemble(PROGRAM).unwrap();
+        let mut network = Network::new(&program, 3);
+        let mut events = Vec::new();
+
+        network.run(|event| {
+            events.push(event);
+
+            match event {
+                Event::Idle => Action::Send(Packet { dest: 2, x: 1, y: 2 }),
+                Event::Packet(packet) if packet.y == 4 => Action::Stop,
+                Event::Packet(_) => Action::Continue,
+            }
+        }).unwrap();
+
+        assert_eq!(events, [
+            Event::Packet(Packet { dest: 255, x: 10, y: 21 }),
+            Event::Idle,
+            Event::Packet(Packet { dest: 255, x: 1, y: 4 }),
+        ]);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{VM, OpCode, Op, VmError};
+
+// Hooks into a VM run. Every hook defaults to doing nothing, so observers
+// only implement what they care about. Pairs of observers are observers
+// themselves and get every hook in order.
+pub trait Observer<Context = ()> {
+    fn before_exec(&mut self, _vm: &mut VM<'_, Context>, _op_code: &OpCode) {}
+
+    fn after_exec(&mut self, _vm: &mut VM<'_, Context>, _op_code: &OpCode) {}
+
+    // Called before an input instruction when no input is available yet,
+    // queueing a value here satisfies it.
+    fn on_input_needed(&mut self, _vm: &mut VM<'_, Context>) {}
+
+    fn on_output(&mut self, _vm: &mut VM<'_, Context>, _value: isize) {}
+
+    fn on_halt(&mut self, _vm: &mut VM<'_, Context>) {}
+}
+
+impl<Context, O: Observer<Context> + ?Sized> Observer<Context> for &mut O {
+    fn before_exec(&mut self, vm: &mut VM<'_, Context>, op_code: &OpCode) {
+        (**self).before_exec(vm, op_code);
+    }
+
+    fn after_exec(&mut self, vm: &mut VM<'_, Context>, op_code: &OpCode) {
+        (**self).after_exec(vm, op_code);
+    }
+
+    fn on_input_needed(&mut self, vm: &mut VM<'_, Context>) {
+        (**self).on_input_needed(vm);
+    }
+
+    fn on_output(&mut self, vm: &mut VM<'_, Context>, value: isize) {
+        (**self).on_output(vm, value);
+    }
+
+    fn on_halt(&mut self, vm: &mut VM<'_, Context>) {
+        (**self).on_halt(vm);
+    }
+}
+
+impl<Context, A: Observer<Context>, B: Observer<Context>> Observer<Context> for (A, B) {
+    fn before_exec(&mut self, vm: &mut VM<'_, Context>, op_code: &OpCode) {
+        self.0.before_exec(vm, op_code);
+        self.1.before_exec(vm, op_code);
+    }
+
+    fn after_exec(&mut self, vm: &mut VM<'_, Context>, op_code: &OpCode) {
+        self.0.after_exec(vm, op_code);
+        self.1.after_exec(vm, op_code);
+    }
+
+    fn on_input_needed(&mut self, vm: &mut VM<'_, Context>) {
+        self.0.on_input_needed(vm);
+        self.1.on_input_needed(vm);
+    }
+
+    fn on_output(&mut self, vm: &mut VM<'_, Context>, value: isize) {
+        self.0.on_output(vm, value);
+        self.1.on_output(vm, value);
+    }
+
+    fn on_halt(&mut self, vm: &mut VM<'_, Context>) {
+        self.0.on_halt(vm);
+        self.1.on_halt(vm);
+    }
+}
+
+struct Tracer<F> {
+    previous: Option<OpCode>,
+    tracer: F,
+}
+
+impl<Context, F> Observer<Context> for Tracer<F>
+where
+    F: FnMut(&mut VM<'_, Context>, Option<&OpCode>, &OpCode),
+{
+    fn before_exec(&mut self, vm: &mut VM<'_, Context>, op_code: &OpCode) {
+        (self.tracer)(vm, self.previous.as_ref(), op_code);
+    }
+
+    fn after_exec(&mut self, _vm: &mut VM<'_, Context>, op_code: &OpCode) {
+        self.previous = Some(op_code.clone());
+    }
+}
+
+impl<'a, Context> VM<'a, Context> {
+    // Runs until the program halts, reporting every step to the observer.
+    // Output hooks run after the instruction that produced the output.
+    pub fn run_observed(&mut self, mut observer: impl Observer<Context>) -> Result<(), VmError> {
+        self.reset_time_limit();
+
+        loop {
+            let op_code = self.next_op_code()?;
+
+            observer.before_exec(self, &op_code);
+
+            if op_code.op() == Op::ReadInput && !self.has_input() {
+                observer.on_input_needed(self);
+            }
+
+            let output_count = self.outputs.len();
+            self.execute(&op_code)?;
+
+            if let Some(&value) = self.outputs.get(output_count) {
+                observer.on_output(self, value);
+            }
+
+            observer.after_exec(self, &op_code);
+
+            if op_code.is_halt() {
+                observer.on_halt(self);
+                return Ok(());
+            }
+        }
+    }
+
+    // Calls `tracer` before every instruction with the previously executed
+    // instruction, which is None for the first one.
+    pub fn run_tracing(&mut self, tracer: impl FnMut(&mut VM<'_, Context>, Option<&OpCode>, &OpCode)) -> Result<(), VmError> {
+        self.run_observed(Tracer { previous: None, tracer })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Observer for Log {
+        fn before_exec(&mut self, _vm: &mut VM<'_>, op_code: &OpCode) {
+            self.0.push(op_code.op().mnemonic().to_owned());
+        }
+
+        fn on_input_needed(&mut self, vm: &mut VM<'_>) {
+            self.0.push("input?".to_owned());
+            vm.queue_input(20);
+        }
+
+        fn on_output(&mut self, _vm: &mut VM<'_>, value: isize) {
+            self.0.push(format!("output {}", value));
+        }
+
+        fn on_halt(&mut self, _vm: &mut VM<'_>) {
+            self.0.push("halt!".to_owned());
+        }
+    }
+
+    #[derive(Default)]
+    struct Counter(usize);
+
+    impl Observer for Counter {
+        fn after_exec(&mut self, _vm: &mut VM<'_>, _op_code: &OpCode) {
+            self.0 += 1;
+        }
+    }
+
+    const PROGRAM: &str = "
+            in [x]
+            add [x], #1, [x]
+            out [x]
+            hlt
+        x: .data 0
+    ";
+
+    #[test]
+    fn hooks_in_order() {
+        let mut vm = VM::new(assemble(PROGRAM).unwrap());
+        let mut log = Log::default();
+        let mut counter = Counter::default();
+
+        vm.run_observed((&mut log, &mut counter)).unwrap();
+
+        assert_eq!(log.0, ["in", "input?", "add", "out", "output 21", "hlt", "halt!"]);
+        assert_eq!(counter.0, 4);
+    }
+
+    #[test]
+    fn tracing_starts_without_previous() {
+        let mut vm = VM::new(assemble(PROGRAM).unwrap());
+        let mut pairs = Vec::new();
+        vm.queue_input(1);
+
+        vm.run_tracing(|_, previous, next| {
+            pairs.push((previous.map(OpCode::op), next.op()));
+        }).unwrap();
+
+        assert_eq!(pairs, [
+            (None, Op::ReadInput),
+            (Some(Op::ReadInput), Op::Add),
+            (Some(Op::Add), Op::WriteOutput),
+            (Some(Op::WriteOutput), Op::Halt),
+        ]);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+
+use crate::{VM, RunState, VmError};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StageTrace {
+    pub inputs: Vec<isize>,
+    pub outputs: Vec<isize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineResult {
+    pub output: Option<isize>,
+    pub traces: Vec<StageTrace>,
+}
+
+struct Stage<'a> {
+    vm: VM<'a>,
+    pending: VecDeque<isize>,
+    trace: StageTrace,
+    halted: bool,
+}
+
+#[derive(Default)]
+pub struct Pipeline<'a> {
+    stages: Vec<Stage<'a>>,
+    feedback: bool,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_phases(program: &[isize], phases: &[isize]) -> Self {
+        phases
+            .iter()
+            .fold(Self::new(), |pipeline, &phase| pipeline.stage(VM::new(program), &[phase]))
+    }
+
+    pub fn stage(mut self, vm: VM<'a>, seed: &[isize]) -> Self {
+        self.stages.push(Stage {
+            vm,
+            pending: seed.iter().copied().collect(),
+            trace: StageTrace::default(),
+            halted: false,
+        });
+        self
+    }
+
+    pub fn feedback(mut self, feedback: bool) -> Self {
+        self.feedback = feedback;
+        self
+    }
+
+    pub fn run(mut self, inputs: &[isize]) -> Result<PipelineResult, VmError> {
+        if let Some(first) = self.stages.first_mut() {
+            first.pending.extend(inputs);
+        }
+
+        let count = self.stages.len();
+
+        while !self.stages.iter().all(|stage| stage.halted) {
+            let mut progressed = false;
+
+            for index in 0..count {
+                while let Some(output) = self.stages[index].advance(&mut progressed)? {
+                    let next = match index + 1 {
+                        next if next < count => Some(next),
+                        _ if self.feedback => Some(0),
+                        _ => None,
+                    };
+
+                    if let Some(next) = next {
+                        self.stages[next].pending.push_back(output);
+                    }
+                }
+            }
+
+            if !progressed {
+                let stuck = self.stages.iter().find(|stage| !stage.halted).expect("stuck stage");
+                return Err(VmError::InputExhausted { ip: stuck.vm.ip() });
+            }
+        }
+
+        let traces = self.stages
+            .into_iter()
+            .map(|stage| stage.trace)
+            .collect::<Vec<_>>();
+
+        Ok(PipelineResult {
+            output: traces.last().and_then(|trace| trace.outputs.last().copied()),
+            traces,
+        })
+    }
+}
+
+impl Stage<'_> {
+    // Runs until the next output, or None once blocked on input or halted
+    fn advance(&mut self, progressed: &mut bool) -> Result<Option<isize>, VmError> {
+        if self.halted {
+            return Ok(None);
+        }
+
+        loop {
+            match self.vm.run_until_blocked()? {
+                RunState::Output(value) => {
+                    *progressed = true;
+                    self.trace.outputs.push(value);
+                    return Ok(Some(value));
+                },
+                RunState::NeedsInput => match self.pending.pop_front() {
+                    Some(value) => {
+                        *progressed = true;
+                        self.trace.inputs.push(value);
+                        self.vm.queue_input(value);
+                    },
+                    None => return Ok(None),
+                },
+                RunState::Halted => {
+                    *progressed = true;
+                    self.halted = true;
+                    return Ok(None);
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear() {
+        let program = [
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ];
+
+        let result = Pipeline::with_phases(&program, &[4, 3, 2, 1, 0]).run(&[0]).unwrap();
+
+        assert_eq!(result.output, Some(43210));
+        assert_eq!(result.traces[0], StageTrace { inputs: vec![4, 0], outputs: vec![4] });
+        assert_eq!(result.traces[4].inputs, [0, 4321]);
+    }
+
+    #[test]
+    fn feedback_loop() {
+        let program = [
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27,
+            1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+
+        let result = Pipeline::with_phases(&program, &[9, 8, 7, 6, 5])
+            .feedback(true)
+            .run(&[0])
+            .unwrap();
+
+        assert_eq!(result.output, Some(139629729));
+        assert_eq!(result.traces[4].outputs.len(), 5);
+    }
+
+    #[test]
+    fn starved_stage() {
+        let result = Pipeline::new()
+            .stage(VM::new(vec![3, 0, 3, 0, 99]), &[1])
+            .run(&[]);
+
+        assert_eq!(result, Err(VmError::InputExhausted { ip: 2 }));
+    }
+}
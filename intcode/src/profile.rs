@@ -0,0 +1,292 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::{VM, Op, OpCode, Mode};
+use crate::disasm::{Instruction, label};
+
+const OPS: [Op; 10] = [
+    Op::Add,
+    Op::Mul,
+    Op::ReadInput,
+    Op::WriteOutput,
+    Op::JumpIfTrue,
+    Op::JumpIfFalse,
+    Op::LessThan,
+    Op::Equals,
+    Op::AdjustRelativeBase,
+    Op::Halt,
+];
+
+// What a single instruction is about to touch, captured before it executes
+// so self-modifying code is still recorded as it was run.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Access {
+    pub(crate) code: isize,
+    pub(crate) args: Vec<isize>,
+    // Resolved address per operand, None for immediates
+    pub(crate) addrs: Vec<Option<usize>>,
+    pub(crate) reads: Vec<usize>,
+    pub(crate) write: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    instructions: u64,
+    ips: HashMap<usize, u64>,
+    ops: HashMap<Op, u64>,
+    reads: HashMap<usize, u64>,
+    writes: HashMap<usize, u64>,
+    root: Option<usize>,
+    // Entry and return address of every call that has not returned yet
+    frames: Vec<(usize, usize)>,
+    stacks: HashMap<Vec<usize>, u64>,
+    // Values written since the last jump, used to spot pushed return addresses
+    written: Vec<isize>,
+}
+
+impl Profile {
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn ip_count(&self, addr: usize) -> u64 {
+        self.ips.get(&addr).copied().unwrap_or(0)
+    }
+
+    pub fn op_count(&self, op: Op) -> u64 {
+        self.ops.get(&op).copied().unwrap_or(0)
+    }
+
+    pub fn read_count(&self, addr: usize) -> u64 {
+        self.reads.get(&addr).copied().unwrap_or(0)
+    }
+
+    pub fn write_count(&self, addr: usize) -> u64 {
+        self.writes.get(&addr).copied().unwrap_or(0)
+    }
+
+    pub fn hot_addresses(&self, count: usize) -> Vec<(usize, u64)> {
+        top(&self.ips, count)
+    }
+
+    pub(crate) fn record(&mut self, ip: usize, op_code: &OpCode, access: &Access, next_ip: usize, written: Option<isize>) {
+        let op = op_code.op();
+
+        self.instructions += 1;
+        *self.ips.entry(ip).or_insert(0) += 1;
+        *self.ops.entry(op).or_insert(0) += 1;
+
+        for &addr in &access.reads {
+            *self.reads.entry(addr).or_insert(0) += 1;
+        }
+
+        if let Some(addr) = access.write {
+            *self.writes.entry(addr).or_insert(0) += 1;
+        }
+
+        let root = *self.root.get_or_insert(ip);
+        let stack = Some(root)
+            .into_iter()
+            .chain(self.frames.iter().map(|&(entry, _)| entry))
+            .collect::<Vec<_>>();
+        *self.stacks.entry(stack).or_insert(0) += 1;
+
+        if op.is_jump() {
+            let return_addr = ip + 3;
+            let taken = next_ip != return_addr;
+
+            // A call pushes the address after the jump and unconditionally jumps
+            // to a constant, a return jumps to an address loaded from memory.
+            if taken && op_code.mode(0) == Mode::Immediate && op_code.mode(1) == Mode::Immediate {
+                if self.written.contains(&(return_addr as isize)) {
+                    self.frames.push((next_ip, return_addr));
+                }
+            } else if taken {
+                if let Some(index) = self.frames.iter().rposition(|&(_, addr)| addr == next_ip) {
+                    self.frames.truncate(index);
+                }
+            }
+
+            self.written.clear();
+        }
+
+        self.written.extend(written);
+    }
+
+    // Lists the hottest instructions with their disassembly, the op histogram
+    // and the most read and written memory cells.
+    pub fn report<Context>(&self, vm: &VM<Context>, count: usize) -> String {
+        let mut report = String::new();
+        let total = self.instructions.max(1) as f64;
+
+        writeln!(report, "instructions: {}", self.instructions).unwrap();
+        writeln!(report).unwrap();
+        writeln!(report, "ops:").unwrap();
+
+        let mut ops = OPS
+            .iter()
+            .map(|&op| (op, self.op_count(op)))
+            .filter(|&(_, count)| count > 0)
+            .collect::<Vec<_>>();
+        ops.sort_by_key(|&(_, count)| Reverse(count));
+
+        for (op, count) in ops {
+            writeln!(report, "  {:<4} {:>12} {:>6.2}%", op.mnemonic(), count, count as f64 * 100.0 / total).unwrap();
+        }
+
+        writeln!(report).unwrap();
+        writeln!(report, "hot addresses:").unwrap();
+
+        for (addr, count) in self.hot_addresses(count) {
+            let instruction = Instruction::decode_with(addr, |addr| Some(vm.read(addr)))
+                .map(|instruction| instruction.to_string())
+                .unwrap_or_else(|| "???".to_owned());
+
+            writeln!(report, "  {:>6} {:>12} {:>6.2}%  {}", addr, count, count as f64 * 100.0 / total, instruction).unwrap();
+        }
+
+        for (title, heat) in &[("memory reads:", &self.reads), ("memory writes:", &self.writes)] {
+            writeln!(report).unwrap();
+            writeln!(report, "{}", title).unwrap();
+
+            for (addr, count) in top(heat, count) {
+                writeln!(report, "  {:>6} {:>12}", addr, count).unwrap();
+            }
+        }
+
+        report
+    }
+
+    // One line per call stack in the folded format used by flamegraph tools.
+    // Frames are named after the entry address of each call.
+    pub fn folded(&self) -> String {
+        let mut stacks = self.stacks.iter().collect::<Vec<_>>();
+        stacks.sort();
+
+        stacks
+            .into_iter()
+            .map(|(stack, count)| {
+                let frames = stack.iter().map(|&addr| label(addr)).collect::<Vec<_>>();
+                format!("{} {}\n", frames.join(";"), count)
+            })
+            .collect()
+    }
+}
+
+fn top(counts: &HashMap<usize, u64>, count: usize) -> Vec<(usize, u64)> {
+    let mut counts = counts.iter().map(|(&addr, &count)| (addr, count)).collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts.truncate(count);
+    counts
+}
+
+impl<'a, Context> VM<'a, Context> {
+    pub fn set_profiling(&mut self, enabled: bool) {
+        match (enabled, &self.profile) {
+            (true, None) => self.profile = Some(Box::default()),
+            (false, _) => self.profile = None,
+            (true, Some(_)) => {},
+        }
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_deref()
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take().map(|profile| *profile)
+    }
+
+    pub(crate) fn access(&self, op_code: &OpCode) -> Access {
+        let op = op_code.op();
+        let arg_count = op.arg_count();
+        let mut access = Access {
+            code: self.mem.read(self.ip),
+            ..Access::default()
+        };
+
+        for index in 0..arg_count {
+            let arg = self.mem.read(self.ip + 1 + index);
+
+            // Overflowing relative addresses fault, so they access nothing
+            let addr = match op_code.mode(index) {
+                Mode::Position => Some(arg),
+                Mode::Relative => self.relative_base.checked_add(arg),
+                Mode::Immediate => None,
+            };
+
+            let addr = addr.filter(|&addr| addr >= 0).map(|addr| addr as usize);
+
+            access.args.push(arg);
+            access.addrs.push(addr);
+
+            match addr {
+                Some(addr) if op.writes() && index == arg_count - 1 => access.write = Some(addr),
+                Some(addr) => access.reads.push(addr),
+                None => {},
+            }
+        }
+
+        access
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // Calls `double` twice through a stack kept at the relative base
+    const PROGRAM: &str = "
+            arb #100
+            add #first, #0, rel[+0]
+            jt #1, #double
+        first:
+            add #second, #0, rel[+0]
+            jt #1, #double
+        second:
+            out [value]
+            hlt
+        double:
+            mul [value], #2, [value]
+            jt #1, rel[+0]
+        value: .data 3
+    ";
+
+    #[test]
+    fn counts_ops_and_calls() {
+        let program = assemble(PROGRAM).unwrap();
+        let double = 19;
+        let value = 26;
+
+        let mut vm = VM::new(program);
+        vm.set_profiling(true);
+        vm.run().unwrap();
+
+        assert_eq!(vm.outputs(), [12]);
+
+        let profile = vm.profile().unwrap();
+
+        assert_eq!(profile.instructions(), 11);
+        assert_eq!(profile.op_count(Op::Mul), 2);
+        assert_eq!(profile.op_count(Op::JumpIfTrue), 4);
+        assert_eq!(profile.ip_count(double), 2);
+        assert_eq!(profile.read_count(value), 3);
+        assert_eq!(profile.write_count(value), 2);
+        assert_eq!(profile.folded(), "L0 7\nL0;L19 4\n");
+
+        let report = profile.report(&vm, 3);
+
+        assert!(report.contains("instructions: 11"));
+        assert!(report.contains("mul [26], #2, [26]"));
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let mut vm = VM::new(vec![99]);
+        vm.run().unwrap();
+
+        assert!(vm.profile().is_none());
+    }
+}
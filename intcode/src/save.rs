@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::{VM, Snapshot, SaveError};
+
+const MAGIC: &str = "intcode-save";
+const VERSION: &str = "2";
+// Version 1 had no segment entries, only memory from address 0
+const VERSION_1: &str = "1";
+const SEGMENT_PREFIX: &str = "mem@";
+
+impl Snapshot {
+    pub fn to_save_string(&self) -> String {
+        let base = self.segments
+            .iter()
+            .find(|(addr, _)| *addr == 0)
+            .map_or(&[][..], |(_, cells)| &cells[..]);
+
+        let mut save = format!(
+            "{} {}\nip {}\nrelative_base {}\nmem {}\ninputs {}\noutputs {}\n",
+            MAGIC,
+            VERSION,
+            self.ip,
+            self.relative_base,
+            join(base),
+            join(&self.inputs),
+            join(&self.outputs),
+        );
+
+        // Segments away from address 0 come from sparse memory backends
+        for (addr, cells) in self.segments.iter().filter(|(addr, _)| *addr != 0) {
+            save += &format!("{}{} {}\n", SEGMENT_PREFIX, addr, join(cells));
+        }
+
+        save
+    }
+
+    pub fn from_save_str(save: &str) -> Result<Self, SaveError> {
+        let mut lines = save.lines().enumerate().map(|(index, line)| (index + 1, line));
+
+        let has_segments = match lines.next().map(|(_, line)| split_entry(line)) {
+            Some((MAGIC, VERSION)) => true,
+            Some((MAGIC, VERSION_1)) => false,
+            Some((MAGIC, version)) => return Err(SaveError::UnsupportedVersion(version.to_owned())),
+            _ => return Err(malformed(1, format!("expected `{} <version>` header", MAGIC))),
+        };
+
+        let mut entries = HashMap::new();
+
+        for (line_number, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (key, value) = split_entry(line);
+
+            if entries.insert(key, (line_number, value)).is_some() {
+                return Err(malformed(line_number, format!("duplicate entry `{}`", key)));
+            }
+        }
+
+        let end = save.lines().count() + 1;
+        let mut field = |key: &str| {
+            entries
+                .remove(key)
+                .ok_or_else(|| malformed(end, format!("missing entry `{}`", key)))
+        };
+
+        let (line, ip) = field("ip")?;
+        let ip = ip.parse().map_err(|_| malformed(line, format!("invalid ip `{}`", ip)))?;
+
+        let (line, relative_base) = field("relative_base")?;
+        let relative_base = relative_base
+            .parse()
+            .map_err(|_| malformed(line, format!("invalid relative base `{}`", relative_base)))?;
+
+        let (line, mem) = field("mem")?;
+        let base = split_values(line, mem)?;
+        let mut segments = Vec::new();
+
+        let (line, inputs) = field("inputs")?;
+        let inputs = split_values(line, inputs)?;
+
+        let (line, outputs) = field("outputs")?;
+        let outputs = split_values(line, outputs)?;
+
+        for (key, (line, cells)) in entries {
+            let addr: usize = match key.strip_prefix(SEGMENT_PREFIX).filter(|_| has_segments) {
+                Some(addr) => addr
+                    .parse()
+                    .map_err(|_| malformed(line, format!("invalid segment address `{}`", addr)))?,
+                None => return Err(malformed(line, format!("unknown entry `{}`", key))),
+            };
+
+            let cells = split_values(line, cells)?;
+
+            // Restoring writes every cell, so the segment must fit the address space
+            if addr.checked_add(cells.len()).is_none() {
+                return Err(malformed(line, format!("segment at {} overflows the address space", addr)));
+            }
+
+            segments.push((addr, cells));
+        }
+
+        // Sparse memory without page 0 is written with an empty `mem` entry,
+        // which only stands for a segment when there is no other memory
+        if !base.is_empty() || segments.is_empty() {
+            segments.push((0, base));
+        }
+
+        segments.sort_by_key(|&(addr, _)| addr);
+
+        Ok(Snapshot { segments, ip, relative_base, inputs, outputs })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        fs::write(path, self.to_save_string())?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SaveError> {
+        Self::from_save_str(&fs::read_to_string(path)?)
+    }
+}
+
+impl<'a, Context> VM<'a, Context> {
+    pub fn save(&mut self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        self.snapshot().save(path)
+    }
+
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        let snapshot = Snapshot::load(path)?;
+        self.restore(&snapshot);
+        Ok(())
+    }
+}
+
+fn join(values: &[isize]) -> String {
+    values
+        .iter()
+        .map(isize::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn split_entry(line: &str) -> (&str, &str) {
+    let line = line.trim();
+
+    match line.find(' ') {
+        Some(index) => (&line[..index], line[index + 1..].trim()),
+        None => (line, ""),
+    }
+}
+
+fn split_values(line: usize, values: &str) -> Result<Vec<isize>, SaveError> {
+    if values.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    values
+        .split(',')
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| malformed(line, format!("invalid value `{}`", value)))
+        })
+        .collect()
+}
+
+fn malformed(line: usize, message: String) -> SaveError {
+    SaveError::Malformed { line, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RunState, PagedMemory};
+    use crate::memory::PAGE_SIZE;
+
+    #[test]
+    fn round_trip() {
+        let mut vm = VM::new(vec![4, 7, 3, 7, 3, 8, 99, 42]);
+        vm.queue_input(5);
+        vm.queue_input(6);
+        vm.run_until_blocked().unwrap();
+
+        let snapshot = vm.snapshot();
+        let save = snapshot.to_save_string();
+
+        assert_eq!(save, "intcode-save 2\nip 2\nrelative_base 0\nmem 4,7,3,7,3,8,99,42\ninputs 5,6\noutputs 42\n");
+        assert_eq!(Snapshot::from_save_str(&save).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let path = std::env::temp_dir().join(format!("intcode-save-test-{}", std::process::id()));
+
+        let mut vm = VM::new(vec![3, 5, 4, 5, 99, 0]);
+        assert_eq!(vm.run_until_blocked(), Ok(RunState::NeedsInput));
+        vm.save(&path).unwrap();
+
+        let mut resumed = VM::new(vec![]);
+        resumed.load(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        resumed.add_input(9);
+        resumed.run().unwrap();
+
+        assert_eq!(resumed.outputs(), [9]);
+    }
+
+    #[test]
+    fn sparse_round_trip() {
+        let mut vm = VM::with_memory(PagedMemory::new(&[99]));
+        vm.write(PAGE_SIZE * 5 + 1, 7);
+
+        let snapshot = vm.snapshot();
+        let save = snapshot.to_save_string();
+        assert!(save.contains(&format!("\nmem@{} 0,7,", PAGE_SIZE * 5)));
+
+        let mut resumed = VM::with_memory(PagedMemory::default());
+        resumed.restore(&Snapshot::from_save_str(&save).unwrap());
+        resumed.run().unwrap();
+
+        assert_eq!(resumed.read(PAGE_SIZE * 5 + 1), 7);
+        assert_eq!(resumed.snapshot(), snapshot);
+
+        // Page 0 was never touched, so the save has an empty `mem` entry
+        let mut vm = VM::with_memory(PagedMemory::default());
+        vm.write(PAGE_SIZE * 3, 5);
+        let snapshot = vm.snapshot();
+
+        assert_eq!(Snapshot::from_save_str(&snapshot.to_save_string()).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn loads_version_1() {
+        let save = "intcode-save 1\nip 2\nrelative_base 3\nmem 4,7,3,7,99\ninputs 5\noutputs 42\n";
+        let snapshot = Snapshot::from_save_str(save).unwrap();
+
+        assert_eq!(snapshot.segments(), [(0, vec![4, 7, 3, 7, 99])]);
+        assert_eq!(snapshot.ip(), 2);
+        assert_eq!(snapshot.relative_base(), 3);
+        assert_eq!(snapshot.inputs(), [5]);
+        assert_eq!(snapshot.outputs(), [42]);
+
+        // Segments only exist since version 2
+        let save = format!("{}mem@1024 1\n", save);
+        assert!(matches!(Snapshot::from_save_str(&save), Err(SaveError::Malformed { line: 7, .. })));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        match Snapshot::from_save_str("intcode-save 99\n") {
+            Err(SaveError::UnsupportedVersion(version)) => assert_eq!(version, "99"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let save = "intcode-save 1\nip x\nrelative_base 0\nmem 99\ninputs\noutputs\n";
+
+        match Snapshot::from_save_str(save) {
+            Err(SaveError::Malformed { line, .. }) => assert_eq!(line, 2),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let save = format!("intcode-save 2\nip 0\nrelative_base 0\nmem 99\ninputs\noutputs\nmem@{} 1,2\n", usize::MAX);
+
+        match Snapshot::from_save_str(&save) {
+            Err(SaveError::Malformed { line, .. }) => assert_eq!(line, 7),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::VM;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub(crate) segments: Vec<(usize, Vec<isize>)>,
+    pub(crate) ip: usize,
+    pub(crate) relative_base: isize,
+    pub(crate) inputs: Vec<isize>,
+    pub(crate) outputs: Vec<isize>,
+}
+
+impl Snapshot {
+    pub fn segments(&self) -> &[(usize, Vec<isize>)] {
+        &self.segments
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    pub fn inputs(&self) -> &[isize] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[isize] {
+        &self.outputs
+    }
+}
+
+impl<'a, Context> VM<'a, Context> {
+    pub fn snapshot(&mut self) -> Snapshot {
+        self.drain_input_channel();
+
+        Snapshot {
+            segments: self.mem
+                .segments()
+                .into_iter()
+                .map(|(addr, cells)| (addr, cells.to_vec()))
+                .collect(),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            inputs: self.input_queue.iter().copied().collect(),
+            outputs: self.outputs.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.drain_input_channel();
+
+        self.mem.load_segments(&snapshot.segments);
+
+        self.ip = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.input_queue = snapshot.inputs.iter().copied().collect();
+        self.outputs.clone_from(&snapshot.outputs);
+        self.history.clear();
+    }
+
+    pub fn fork(&mut self) -> Self
+    where
+        Context: Clone,
+    {
+        let snapshot = self.snapshot();
+        let mut vm = Self::with_boxed_memory(self.mem.new_empty(), self.context.clone());
+
+        vm.restore(&snapshot);
+        vm.debug = self.debug;
+        vm.overflow_policy = self.overflow_policy;
+        vm.watchdog = self.watchdog.clone();
+        vm.did_run = self.did_run;
+        vm
+    }
+
+    // Queued inputs take priority over the channel,
+    // so appending the channel keeps the input order intact.
+    pub(crate) fn drain_input_channel(&mut self) {
+        while let Ok(value) = self.input_rx.try_recv() {
+            self.input_queue.push_back(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{VM, RunState};
+
+    const ECHO: [isize; 6] = [3, 5, 4, 5, 99, 0];
+
+    #[test]
+    fn restore() {
+        let mut vm = VM::new(ECHO.to_vec());
+        vm.add_input(1);
+
+        let snapshot = vm.snapshot();
+        vm.run().unwrap();
+        assert_eq!(vm.outputs(), [1]);
+
+        vm.restore(&snapshot);
+        assert_eq!(vm.outputs(), []);
+        assert_eq!(vm.ip(), 0);
+
+        vm.run().unwrap();
+        assert_eq!(vm.outputs(), [1]);
+    }
+
+    #[test]
+    fn fork() {
+        let mut vm = VM::new(ECHO.to_vec());
+        assert_eq!(vm.run_until_blocked(), Ok(RunState::NeedsInput));
+
+        let mut fork = vm.fork();
+
+        vm.add_input(1);
+        fork.add_input(2);
+
+        vm.run().unwrap();
+        fork.run().unwrap();
+
+        assert_eq!(vm.outputs(), [1]);
+        assert_eq!(fork.outputs(), [2]);
+    }
+}
@@ -0,0 +1,658 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use crate::{Op, OpCode, Mode, OverflowPolicy, VmError, SymbolicError};
+
+const STEP_LIMIT: usize = 10_000_000;
+const SEARCH_LIMIT: usize = 1_000_000;
+// Re-runs after a solved candidate took a different path
+const ROUNDS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Const(isize),
+    Var(usize),
+    Add(Rc<Expr>, Rc<Expr>),
+    Mul(Rc<Expr>, Rc<Expr>),
+    LessThan(Rc<Expr>, Rc<Expr>),
+    Equals(Rc<Expr>, Rc<Expr>),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Var(var) => write!(f, "x{}", var),
+            Expr::Add(a, b) => write!(f, "({} + {})", a, b),
+            Expr::Mul(a, b) => write!(f, "({} * {})", a, b),
+            Expr::LessThan(a, b) => write!(f, "({} < {})", a, b),
+            Expr::Equals(a, b) => write!(f, "({} == {})", a, b),
+        }
+    }
+}
+
+// `constant + sum(coefficient * var)`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Linear {
+    pub coefficients: BTreeMap<usize, isize>,
+    pub constant: isize,
+}
+
+impl Expr {
+    pub fn is_const(&self) -> bool {
+        matches!(self, Expr::Const(_))
+    }
+
+    // None if the expression isn't linear or the coefficients overflow
+    pub fn linear(&self) -> Option<Linear> {
+        self.linear_memo(&mut HashMap::new())
+    }
+
+    // Expressions are DAGs, so shared subtrees are only visited once
+    fn linear_memo(&self, memo: &mut HashMap<*const Expr, Option<Linear>>) -> Option<Linear> {
+        let key = self as *const Expr;
+
+        if let Some(linear) = memo.get(&key) {
+            return linear.clone();
+        }
+
+        let linear = match self {
+            Expr::Const(value) => Some(Linear { coefficients: BTreeMap::new(), constant: *value }),
+            Expr::Var(var) => Some(Linear { coefficients: Some((*var, 1)).into_iter().collect(), constant: 0 }),
+            Expr::Add(a, b) => match (a.linear_memo(memo), b.linear_memo(memo)) {
+                (Some(a), Some(b)) => a.add(&b),
+                _ => None,
+            },
+            Expr::Mul(a, b) => match (a.linear_memo(memo), b.linear_memo(memo)) {
+                (Some(a), Some(b)) if b.coefficients.is_empty() => a.scale(b.constant),
+                (Some(a), Some(b)) if a.coefficients.is_empty() => b.scale(a.constant),
+                _ => None,
+            },
+            Expr::LessThan(..) | Expr::Equals(..) => None,
+        };
+
+        memo.insert(key, linear.clone());
+        linear
+    }
+}
+
+impl Linear {
+    fn add(&self, other: &Linear) -> Option<Linear> {
+        let mut sum = self.clone();
+        sum.constant = sum.constant.checked_add(other.constant)?;
+
+        for (&var, &coefficient) in &other.coefficients {
+            let entry = sum.coefficients.entry(var).or_insert(0);
+            *entry = entry.checked_add(coefficient)?;
+        }
+
+        sum.coefficients.retain(|_, coefficient| *coefficient != 0);
+        Some(sum)
+    }
+
+    fn scale(&self, factor: isize) -> Option<Linear> {
+        let mut coefficients = BTreeMap::new();
+
+        for (&var, &coefficient) in &self.coefficients {
+            if factor != 0 {
+                coefficients.insert(var, coefficient.checked_mul(factor)?);
+            }
+        }
+
+        Some(Linear {
+            coefficients,
+            constant: self.constant.checked_mul(factor)?,
+        })
+    }
+
+    fn eval(&self, assignment: &[isize]) -> Option<isize> {
+        self.coefficients.iter().try_fold(self.constant, |sum, (&var, &coefficient)| {
+            sum.checked_add(coefficient.checked_mul(assignment[var])?)
+        })
+    }
+}
+
+// A cell or input value together with how it was derived from the variables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Value {
+    pub concrete: isize,
+    pub expr: Rc<Expr>,
+}
+
+impl Value {
+    fn constant(value: isize) -> Self {
+        Self {
+            concrete: value,
+            expr: Rc::new(Expr::Const(value)),
+        }
+    }
+
+    fn var(var: usize, value: isize) -> Self {
+        Self {
+            concrete: value,
+            expr: Rc::new(Expr::Var(var)),
+        }
+    }
+
+    fn combine(&self, other: &Value, concrete: isize, build: impl FnOnce(Rc<Expr>, Rc<Expr>) -> Expr) -> Self {
+        let expr = match (&*self.expr, &*other.expr) {
+            (a, b) if a.is_const() && b.is_const() => Expr::Const(concrete),
+            _ => build(self.expr.clone(), other.expr.clone()),
+        };
+
+        Self {
+            concrete,
+            expr: Rc::new(expr),
+        }
+    }
+}
+
+// A symbolic value that had to be fixed to its concrete value to continue,
+// e.g. because it was used as an address or a jump condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub expr: Rc<Expr>,
+    pub value: isize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Run {
+    mem: Vec<Value>,
+    outputs: Vec<Value>,
+    constraints: Vec<Constraint>,
+}
+
+impl Run {
+    pub fn read(&self, addr: usize) -> Value {
+        self.mem.get(addr).cloned().unwrap_or_else(|| Value::constant(0))
+    }
+
+    pub fn outputs(&self) -> &[Value] {
+        &self.outputs
+    }
+
+    pub fn constraints(&self) -> &[Constraint] {
+        &self.constraints
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Target {
+    // The cell's value once the program halts
+    Memory(usize),
+    Output(usize),
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Source {
+    Concrete(isize),
+    Symbolic(usize),
+}
+
+pub struct Solver {
+    program: Vec<isize>,
+    cells: Vec<(usize, usize)>,
+    inputs: Vec<Source>,
+    domains: Vec<RangeInclusive<isize>>,
+    step_limit: usize,
+    search_limit: usize,
+}
+
+impl Solver {
+    pub fn new(program: impl Into<Vec<isize>>) -> Self {
+        Self {
+            program: program.into(),
+            cells: Vec::new(),
+            inputs: Vec::new(),
+            domains: Vec::new(),
+            step_limit: STEP_LIMIT,
+            search_limit: SEARCH_LIMIT,
+        }
+    }
+
+    // Variables are numbered in the order they are declared
+    pub fn symbolic_memory(mut self, addr: usize, domain: RangeInclusive<isize>) -> Self {
+        self.cells.push((addr, self.domains.len()));
+        self.domains.push(domain);
+        self
+    }
+
+    pub fn symbolic_input(mut self, domain: RangeInclusive<isize>) -> Self {
+        self.inputs.push(Source::Symbolic(self.domains.len()));
+        self.domains.push(domain);
+        self
+    }
+
+    pub fn input(mut self, value: isize) -> Self {
+        self.inputs.push(Source::Concrete(value));
+        self
+    }
+
+    pub fn step_limit(mut self, limit: usize) -> Self {
+        self.step_limit = limit;
+        self
+    }
+
+    pub fn search_limit(mut self, limit: usize) -> Self {
+        self.search_limit = limit;
+        self
+    }
+
+    pub fn var_count(&self) -> usize {
+        self.domains.len()
+    }
+
+    pub fn execute(&self, assignment: &[isize]) -> Result<Run, SymbolicError> {
+        assert_eq!(assignment.len(), self.domains.len(), "one value per variable");
+
+        let mut exec = Exec {
+            mem: self.program.iter().map(|&value| Value::constant(value)).collect(),
+            ip: 0,
+            relative_base: 0,
+            constraints: Vec::new(),
+        };
+
+        for &(addr, var) in &self.cells {
+            exec.write(addr, Value::var(var, assignment[var]));
+        }
+
+        let mut inputs = self.inputs.iter().map(|&source| match source {
+            Source::Concrete(value) => Value::constant(value),
+            Source::Symbolic(var) => Value::var(var, assignment[var]),
+        });
+        let mut outputs = Vec::new();
+
+        for _ in 0..self.step_limit {
+            let ip = exec.ip;
+            let code = exec.fixed(ip);
+            let op_code = OpCode::parse(code).map_err(|err| err.at(ip))?;
+            let modes = op_code.modes();
+
+            match op_code.op() {
+                Op::Add => {
+                    let (a, b) = (exec.arg(1, modes)?, exec.arg(2, modes)?);
+                    let value = OverflowPolicy::Checked.add(ip, a.concrete, b.concrete)?;
+                    exec.write_arg(3, a.combine(&b, value, Expr::Add), modes)?;
+                    exec.ip += 4;
+                },
+                Op::Mul => {
+                    let (a, b) = (exec.arg(1, modes)?, exec.arg(2, modes)?);
+                    let value = OverflowPolicy::Checked.mul(ip, a.concrete, b.concrete)?;
+                    exec.write_arg(3, a.combine(&b, value, Expr::Mul), modes)?;
+                    exec.ip += 4;
+                },
+                Op::LessThan => {
+                    let (a, b) = (exec.arg(1, modes)?, exec.arg(2, modes)?);
+                    let value = (a.concrete < b.concrete) as isize;
+                    exec.write_arg(3, a.combine(&b, value, Expr::LessThan), modes)?;
+                    exec.ip += 4;
+                },
+                Op::Equals => {
+                    let (a, b) = (exec.arg(1, modes)?, exec.arg(2, modes)?);
+                    let value = (a.concrete == b.concrete) as isize;
+                    exec.write_arg(3, a.combine(&b, value, Expr::Equals), modes)?;
+                    exec.ip += 4;
+                },
+                Op::ReadInput => {
+                    let value = inputs.next().ok_or(VmError::InputExhausted { ip })?;
+                    exec.write_arg(1, value, modes)?;
+                    exec.ip += 2;
+                },
+                Op::WriteOutput => {
+                    outputs.push(exec.arg(1, modes)?);
+                    exec.ip += 2;
+                },
+                Op::JumpIfTrue | Op::JumpIfFalse => {
+                    let cond = exec.arg(1, modes)?;
+                    let cond = exec.fix(&cond) != 0;
+                    let target = exec.arg(2, modes)?;
+                    let target = exec.fix(&target);
+
+                    if cond == (op_code.op() == Op::JumpIfTrue) {
+                        exec.ip = exec.check_addr(target)?;
+                    } else {
+                        exec.ip += 3;
+                    }
+                },
+                Op::AdjustRelativeBase => {
+                    let adjustment = exec.arg(1, modes)?;
+                    let adjustment = exec.fix(&adjustment);
+                    exec.relative_base = OverflowPolicy::Checked.add(ip, exec.relative_base, adjustment)?;
+                    exec.ip += 2;
+                },
+                Op::Halt => {
+                    return Ok(Run {
+                        mem: exec.mem,
+                        outputs,
+                        constraints: exec.constraints,
+                    });
+                },
+            }
+        }
+
+        Err(SymbolicError::StepLimit)
+    }
+
+    // Finds values for the variables that make `target` equal `value`.
+    // Linear targets are solved directly, everything else falls back to
+    // trying assignments in order up to the search limit.
+    pub fn solve(&self, target: Target, value: isize) -> Result<Option<Vec<isize>>, SymbolicError> {
+        let mut assignment = self.domains.iter().map(|domain| *domain.start()).collect::<Vec<_>>();
+        // The starting assignment may crash or take a path without the target
+        // while others don't, so failing here only rules out solving directly
+        let start = self.execute(&assignment).and_then(|run| target_value(&run, target));
+        let mut result = start.as_ref().ok().cloned();
+
+        for _ in 0..ROUNDS {
+            let current = match &result {
+                Some(current) => current,
+                None => break,
+            };
+
+            if current.concrete == value {
+                return Ok(Some(assignment));
+            }
+
+            let candidate = match current.expr.linear().and_then(|linear| self.solve_linear(&linear, value, &assignment)) {
+                Some(candidate) if candidate != assignment => candidate,
+                _ => break,
+            };
+
+            // The candidate may take another path, in which case its own
+            // expression is solved again.
+            result = match self.execute(&candidate).and_then(|run| target_value(&run, target)) {
+                Ok(result) => Some(result),
+                Err(_) => break,
+            };
+            assignment = candidate;
+        }
+
+        // The last round's candidate hasn't been compared yet
+        if result.is_some_and(|result| result.concrete == value) {
+            return Ok(Some(assignment));
+        }
+
+        let mut found = None;
+
+        for_each_assignment(&self.domains, self.search_limit, |assignment| {
+            let hit = self
+                .execute(assignment)
+                .and_then(|run| target_value(&run, target))
+                .is_ok_and(|result| result.concrete == value);
+
+            if hit {
+                found = Some(assignment.to_vec());
+            }
+
+            hit
+        });
+
+        match (found, start) {
+            (None, Err(err)) => Err(err),
+            (found, _) => Ok(found),
+        }
+    }
+
+    // Enumerates every variable but the one with the largest coefficient
+    // and solves for that one exactly.
+    fn solve_linear(&self, linear: &Linear, value: isize, assignment: &[isize]) -> Option<Vec<isize>> {
+        let (&pivot, &coefficient) = linear.coefficients.iter().max_by_key(|(_, coefficient)| coefficient.abs())?;
+        let others = linear.coefficients.keys().copied().filter(|&var| var != pivot).collect::<Vec<_>>();
+        let domains = others.iter().map(|&var| self.domains[var].clone()).collect::<Vec<_>>();
+        let mut candidate = assignment.to_vec();
+        let mut found = None;
+
+        for_each_assignment(&domains, self.search_limit, |values| {
+            for (&var, &value) in others.iter().zip(values) {
+                candidate[var] = value;
+            }
+
+            candidate[pivot] = 0;
+
+            let rest = match linear.eval(&candidate).and_then(|sum| value.checked_sub(sum)) {
+                Some(rest) => rest,
+                None => return false,
+            };
+
+            // isize::MIN / -1 overflows, which leaves no solution either
+            let pivot_value = match (rest.checked_rem(coefficient), rest.checked_div(coefficient)) {
+                (Some(0), Some(pivot_value)) if self.domains[pivot].contains(&pivot_value) => pivot_value,
+                _ => return false,
+            };
+
+            candidate[pivot] = pivot_value;
+            found = Some(candidate.clone());
+            true
+        });
+
+        found
+    }
+}
+
+fn target_value(run: &Run, target: Target) -> Result<Value, SymbolicError> {
+    match target {
+        Target::Memory(addr) => Ok(run.read(addr)),
+        Target::Output(index) => run.outputs.get(index).cloned().ok_or(SymbolicError::MissingOutput(index)),
+    }
+}
+
+// Calls `f` with every combination of values until it returns true
+// or `limit` combinations were tried.
+fn for_each_assignment(domains: &[RangeInclusive<isize>], limit: usize, mut f: impl FnMut(&[isize]) -> bool) {
+    if domains.iter().any(|domain| domain.is_empty()) {
+        return;
+    }
+
+    let mut values = domains.iter().map(|domain| *domain.start()).collect::<Vec<_>>();
+
+    for _ in 0..limit {
+        if f(&values) {
+            return;
+        }
+
+        let mut index = values.len();
+
+        loop {
+            if index == 0 {
+                return;
+            }
+
+            index -= 1;
+
+            if values[index] < *domains[index].end() {
+                values[index] += 1;
+                break;
+            }
+
+            values[index] = *domains[index].start();
+        }
+    }
+}
+
+struct Exec {
+    mem: Vec<Value>,
+    ip: usize,
+    relative_base: isize,
+    constraints: Vec<Constraint>,
+}
+
+impl Exec {
+    fn read(&self, addr: usize) -> Value {
+        self.mem.get(addr).cloned().unwrap_or_else(|| Value::constant(0))
+    }
+
+    fn write(&mut self, addr: usize, value: Value) {
+        if addr >= self.mem.len() {
+            self.mem.resize(addr + 1, Value::constant(0));
+        }
+
+        self.mem[addr] = value;
+    }
+
+    fn fix(&mut self, value: &Value) -> isize {
+        if !value.expr.is_const() {
+            self.constraints.push(Constraint {
+                expr: value.expr.clone(),
+                value: value.concrete,
+            });
+        }
+
+        value.concrete
+    }
+
+    fn fixed(&mut self, addr: usize) -> isize {
+        let value = self.read(addr);
+        self.fix(&value)
+    }
+
+    fn check_addr(&self, addr: isize) -> Result<usize, VmError> {
+        if addr < 0 {
+            return Err(VmError::NegativeAddress { ip: self.ip, addr });
+        }
+
+        Ok(addr as usize)
+    }
+
+    fn addr(&mut self, index: usize, modes: &[Mode]) -> Result<Option<usize>, VmError> {
+        let arg = self.fixed(self.ip + index);
+
+        let addr = match modes.get(index - 1).unwrap_or(&Mode::Position) {
+            Mode::Position => arg,
+            Mode::Immediate => return Ok(None),
+            Mode::Relative => OverflowPolicy::Checked.add(self.ip, self.relative_base, arg)?,
+        };
+
+        self.check_addr(addr).map(Some)
+    }
+
+    fn arg(&mut self, index: usize, modes: &[Mode]) -> Result<Value, VmError> {
+        match self.addr(index, modes)? {
+            Some(addr) => Ok(self.read(addr)),
+            None => Ok(self.read(self.ip + index)),
+        }
+    }
+
+    fn write_arg(&mut self, index: usize, value: Value, modes: &[Mode]) -> Result<(), VmError> {
+        match self.addr(index, modes)? {
+            Some(addr) => {
+                self.write(addr, value);
+                Ok(())
+            },
+            None => Err(VmError::WriteToImmediate { ip: self.ip }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn solves_linear_memory_directly() {
+        // Like day 2: the result is linear in the two patched cells
+        let program = assemble("
+                mul [noun], #300, [t]
+                add [t], [verb], [t]
+                add [t], #5, [result]
+                hlt
+            noun: .data 0
+            verb: .data 0
+            t: .data 0
+            result: .data 0
+        ").unwrap();
+
+        let solver = Solver::new(program)
+            .symbolic_memory(13, 0..=99)
+            .symbolic_memory(14, 0..=99)
+            .search_limit(200);
+
+        let run = solver.execute(&[1, 2]).unwrap();
+        assert_eq!(run.read(16).expr.linear(), Some(Linear {
+            coefficients: vec![(0, 300), (1, 1)].into_iter().collect(),
+            constant: 5,
+        }));
+
+        assert_eq!(solver.solve(Target::Memory(16), 300 * 42 + 17 + 5), Ok(Some(vec![42, 17])));
+        assert_eq!(solver.solve(Target::Memory(16), -1), Ok(None));
+    }
+
+    #[test]
+    fn symbolic_input_and_constraints() {
+        let program = assemble("
+                in [x]
+                lt [x], #10, [t]
+                jt [t], #small
+                mul [x], #3, [x]
+                add [x], #-4, [x]
+            small:
+                out [x]
+                hlt
+            x: .data 0
+            t: .data 0
+        ").unwrap();
+
+        let solver = Solver::new(program).symbolic_input(10..=99);
+        let run = solver.execute(&[12]).unwrap();
+
+        assert_eq!(run.outputs()[0].concrete, 32);
+        assert_eq!(run.outputs()[0].expr.to_string(), "((x0 * 3) + -4)");
+        assert_eq!(run.constraints().len(), 1);
+        assert_eq!(run.constraints()[0].expr.to_string(), "(x0 < 10)");
+
+        assert_eq!(solver.solve(Target::Output(0), 95), Ok(Some(vec![33])));
+    }
+
+    #[test]
+    fn falls_back_to_search() {
+        let program = assemble("
+                in [x]
+                mul [x], [x], [x]
+                out [x]
+                hlt
+            x: .data 0
+        ").unwrap();
+
+        let solver = Solver::new(program).symbolic_input(0..=20);
+
+        assert_eq!(solver.solve(Target::Output(0), 49), Ok(Some(vec![7])));
+        assert_eq!(solver.solve(Target::Output(0), 50), Ok(None));
+        assert_eq!(solver.solve(Target::Output(1), 50), Err(SymbolicError::MissingOutput(1)));
+    }
+
+    #[test]
+    fn solves_at_the_edge_of_isize() {
+        let program = assemble("
+                in [x]
+                mul [x], #-1, [x]
+                out [x]
+                hlt
+            x: .data 0
+        ").unwrap();
+
+        let solver = Solver::new(program).symbolic_input(-5..=5);
+
+        assert_eq!(solver.solve(Target::Output(0), isize::MIN), Ok(None));
+        assert_eq!(solver.solve(Target::Output(0), -3), Ok(Some(vec![3])));
+    }
+
+    #[test]
+    fn searches_when_the_start_crashes() {
+        let program = assemble("
+                in [x]
+                jf [x], #crash
+                mul [x], #2, [x]
+                out [x]
+                hlt
+            crash: .data 42
+            x: .data 0
+        ").unwrap();
+
+        let solver = Solver::new(program).symbolic_input(0..=20);
+
+        assert_eq!(solver.solve(Target::Output(0), 14), Ok(Some(vec![7])));
+        assert!(matches!(solver.solve(Target::Output(0), 15), Err(SymbolicError::Vm(_))));
+    }
+}
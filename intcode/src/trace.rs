@@ -0,0 +1,587 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{VM, Snapshot, Memory, PagedMemory, VmError, TraceError};
+
+const MAGIC: &[u8] = b"ICTR";
+const VERSION: u8 = 1;
+
+const HAS_WRITE: u8 = 1;
+const HAS_INPUT: u8 = 2;
+const HAS_OUTPUT: u8 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub addr: usize,
+    pub old: isize,
+    pub new: isize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub ip: usize,
+    pub code: isize,
+    pub args: Vec<isize>,
+    // Resolved address per operand, None for immediates
+    pub addrs: Vec<Option<usize>>,
+    pub relative_base: isize,
+    pub next_relative_base: isize,
+    pub write: Option<MemoryWrite>,
+    pub input: Option<isize>,
+    pub output: Option<isize>,
+    pub next_ip: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    start: Snapshot,
+    entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    pub fn new(start: Snapshot) -> Self {
+        Self {
+            start,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn start(&self) -> &Snapshot {
+        &self.start
+    }
+
+    pub fn entries(&self) -> &[TraceEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn record(&mut self, entry: TraceEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn replay(&self) -> Replay<'_> {
+        Replay::new(self)
+    }
+
+    // Index of the first entry where the two runs differ
+    pub fn divergence(&self, other: &Trace) -> Option<usize> {
+        let common = self.entries.len().min(other.entries.len());
+
+        self.entries
+            .iter()
+            .zip(&other.entries)
+            .position(|(a, b)| a != b)
+            .or(if self.entries.len() != other.entries.len() { Some(common) } else { None })
+    }
+
+    // Runs the program again from the recorded start, feeding it the
+    // recorded inputs, and returns where the new run stops matching.
+    pub fn reexecute(&self) -> Result<Option<usize>, VmError> {
+        // Paged like `Replay`, so far segments don't allocate everything below them
+        let mut vm = VM::with_memory(PagedMemory::new(&[]));
+        vm.restore(&self.start);
+
+        // Inputs queued at the start are part of the snapshot already
+        for input in self.entries.iter().filter_map(|entry| entry.input).skip(self.start.inputs().len()) {
+            vm.queue_input(input);
+        }
+
+        vm.start_recording();
+
+        for _ in 0..self.entries.len() {
+            vm.step()?;
+        }
+
+        let trace = vm.stop_recording().expect("recording");
+
+        Ok(self.divergence(&trace))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+
+        write_unsigned(&mut out, self.start.ip() as u64);
+        write_signed(&mut out, self.start.relative_base() as i64);
+        write_unsigned(&mut out, self.start.segments().len() as u64);
+
+        for (addr, cells) in self.start.segments() {
+            write_unsigned(&mut out, *addr as u64);
+            write_values(&mut out, cells);
+        }
+
+        write_values(&mut out, self.start.inputs());
+        write_values(&mut out, self.start.outputs());
+        write_unsigned(&mut out, self.entries.len() as u64);
+
+        for entry in &self.entries {
+            write_unsigned(&mut out, entry.ip as u64);
+            write_signed(&mut out, entry.code as i64);
+            write_values(&mut out, &entry.args);
+
+            for addr in &entry.addrs {
+                write_unsigned(&mut out, addr.map_or(0, |addr| addr as u64 + 1));
+            }
+
+            write_signed(&mut out, entry.relative_base as i64);
+            write_signed(&mut out, entry.next_relative_base.wrapping_sub(entry.relative_base) as i64);
+
+            let mut flags = 0;
+            if entry.write.is_some() { flags |= HAS_WRITE; }
+            if entry.input.is_some() { flags |= HAS_INPUT; }
+            if entry.output.is_some() { flags |= HAS_OUTPUT; }
+            out.push(flags);
+
+            if let Some(write) = entry.write {
+                write_unsigned(&mut out, write.addr as u64);
+                write_signed(&mut out, write.old as i64);
+                write_signed(&mut out, write.new as i64);
+            }
+
+            for value in entry.input.iter().chain(&entry.output) {
+                write_signed(&mut out, *value as i64);
+            }
+
+            write_unsigned(&mut out, entry.next_ip as u64);
+        }
+
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TraceError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(malformed(0, "missing trace header"));
+        }
+
+        let mut reader = Reader { bytes, offset: MAGIC.len() };
+
+        match reader.byte()? {
+            VERSION => {},
+            version => return Err(TraceError::UnsupportedVersion(version)),
+        }
+
+        let ip = reader.unsigned()? as usize;
+        let relative_base = reader.signed()? as isize;
+        let segment_count = reader.unsigned()?;
+        let mut segments = Vec::new();
+
+        for _ in 0..segment_count {
+            let offset = reader.offset;
+            let addr = reader.unsigned()? as usize;
+            let cells = reader.values()?;
+
+            // Replay writes every cell, so the segment must fit the address space
+            if addr.checked_add(cells.len()).is_none() {
+                return Err(malformed(offset, format!("segment at {} overflows the address space", addr)));
+            }
+
+            segments.push((addr, cells));
+        }
+
+        let start = Snapshot {
+            segments,
+            ip,
+            relative_base,
+            inputs: reader.values()?,
+            outputs: reader.values()?,
+        };
+
+        let entry_count = reader.unsigned()?;
+        let mut entries = Vec::new();
+
+        for _ in 0..entry_count {
+            let ip = reader.unsigned()? as usize;
+            let code = reader.signed()? as isize;
+            let args = reader.values()?;
+            let addrs = (0..args.len())
+                .map(|_| reader.unsigned().map(|addr| addr.checked_sub(1).map(|addr| addr as usize)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let relative_base = reader.signed()? as isize;
+            // Mirrors the encoder, runs with wrapping overflow can wrap the base
+            let next_relative_base = relative_base.wrapping_add(reader.signed()? as isize);
+
+            let offset = reader.offset;
+            let flags = reader.byte()?;
+
+            if flags & !(HAS_WRITE | HAS_INPUT | HAS_OUTPUT) != 0 {
+                return Err(malformed(offset, format!("invalid entry flags {:#x}", flags)));
+            }
+
+            let write = match flags & HAS_WRITE {
+                0 => None,
+                _ => Some(MemoryWrite {
+                    addr: reader.unsigned()? as usize,
+                    old: reader.signed()? as isize,
+                    new: reader.signed()? as isize,
+                }),
+            };
+
+            let input = if flags & HAS_INPUT != 0 { Some(reader.signed()? as isize) } else { None };
+            let output = if flags & HAS_OUTPUT != 0 { Some(reader.signed()? as isize) } else { None };
+            let next_ip = reader.unsigned()? as usize;
+
+            entries.push(TraceEntry {
+                ip,
+                code,
+                args,
+                addrs,
+                relative_base,
+                next_relative_base,
+                write,
+                input,
+                output,
+                next_ip,
+            });
+        }
+
+        if reader.offset != bytes.len() {
+            return Err(malformed(reader.offset, "trailing bytes"));
+        }
+
+        Ok(Self { start, entries })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TraceError> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TraceError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+// Walks a trace in either direction, reconstructing the machine state
+// between instructions without executing anything.
+pub struct Replay<'t> {
+    trace: &'t Trace,
+    mem: PagedMemory,
+    position: usize,
+    outputs: Vec<isize>,
+}
+
+impl<'t> Replay<'t> {
+    pub fn new(trace: &'t Trace) -> Self {
+        let mut mem = PagedMemory::new(&[]);
+
+        for (addr, cells) in trace.start.segments() {
+            for (offset, &value) in cells.iter().enumerate() {
+                mem.write(addr + offset, value);
+            }
+        }
+
+        Self {
+            trace,
+            mem,
+            position: 0,
+            outputs: trace.start.outputs().to_vec(),
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.position == self.trace.entries.len()
+    }
+
+    pub fn ip(&self) -> usize {
+        match self.position.checked_sub(1) {
+            Some(index) => self.trace.entries[index].next_ip,
+            None => self.trace.start.ip(),
+        }
+    }
+
+    pub fn relative_base(&self) -> isize {
+        match self.position.checked_sub(1) {
+            Some(index) => self.trace.entries[index].next_relative_base,
+            None => self.trace.start.relative_base(),
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> isize {
+        self.mem.read(addr)
+    }
+
+    pub fn outputs(&self) -> &[isize] {
+        &self.outputs
+    }
+
+    // The instruction about to be replayed
+    pub fn next_entry(&self) -> Option<&'t TraceEntry> {
+        self.trace.entries.get(self.position)
+    }
+
+    pub fn step_forward(&mut self) -> Option<&'t TraceEntry> {
+        let entry = self.trace.entries.get(self.position)?;
+
+        if let Some(write) = entry.write {
+            self.mem.write(write.addr, write.new);
+        }
+
+        self.outputs.extend(entry.output);
+        self.position += 1;
+
+        Some(entry)
+    }
+
+    pub fn step_back(&mut self) -> Option<&'t TraceEntry> {
+        self.position = self.position.checked_sub(1)?;
+        let entry = &self.trace.entries[self.position];
+
+        if let Some(write) = entry.write {
+            self.mem.write(write.addr, write.old);
+        }
+
+        if entry.output.is_some() {
+            self.outputs.pop();
+        }
+
+        Some(entry)
+    }
+
+    pub fn seek(&mut self, position: usize) {
+        let position = position.min(self.trace.entries.len());
+
+        while self.position < position {
+            self.step_forward();
+        }
+
+        while self.position > position {
+            self.step_back();
+        }
+    }
+}
+
+impl<'a, Context> VM<'a, Context> {
+    pub fn start_recording(&mut self) {
+        let start = self.snapshot();
+        self.trace = Some(Box::new(Trace::new(start)));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Trace> {
+        self.trace.take().map(|trace| *trace)
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_deref()
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, TraceError> {
+        let byte = *self.bytes.get(self.offset).ok_or_else(|| malformed(self.offset, "unexpected end of trace"))?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn unsigned(&mut self) -> Result<u64, TraceError> {
+        let start = self.offset;
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(malformed(start, "varint too long"))
+    }
+
+    fn signed(&mut self) -> Result<i64, TraceError> {
+        let value = self.unsigned()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn values(&mut self) -> Result<Vec<isize>, TraceError> {
+        let len = self.unsigned()?;
+
+        (0..len).map(|_| self.signed().map(|value| value as isize)).collect()
+    }
+}
+
+fn write_unsigned(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+fn write_signed(out: &mut Vec<u8>, value: i64) {
+    write_unsigned(out, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+fn write_values(out: &mut Vec<u8>, values: &[isize]) {
+    write_unsigned(out, values.len() as u64);
+
+    for &value in values {
+        write_signed(out, value as i64);
+    }
+}
+
+fn malformed(offset: usize, message: impl Into<String>) -> TraceError {
+    TraceError::Malformed { offset, message: message.into() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OverflowPolicy;
+    use crate::asm::assemble;
+
+    // Sums inputs until it reads a zero, printing the running total
+    const PROGRAM: &str = "
+        loop:
+            in [value]
+            jf [value], #done
+            add [sum], [value], [sum]
+            out [sum]
+            jt #1, #loop
+        done:
+            hlt
+        value: .data 0
+        sum: .data 0
+    ";
+
+    fn record(inputs: &[isize]) -> Trace {
+        let mut vm = VM::new(assemble(PROGRAM).unwrap());
+
+        for &input in inputs {
+            vm.queue_input(input);
+        }
+
+        vm.start_recording();
+        vm.run().unwrap();
+        vm.stop_recording().unwrap()
+    }
+
+    #[test]
+    fn records_instructions() {
+        let trace = record(&[2, 3, 0]);
+
+        assert_eq!(trace.len(), 13);
+        assert_eq!(trace.entries()[0], TraceEntry {
+            ip: 0,
+            code: 3,
+            args: vec![15],
+            addrs: vec![Some(15)],
+            relative_base: 0,
+            next_relative_base: 0,
+            write: Some(MemoryWrite { addr: 15, old: 0, new: 2 }),
+            input: Some(2),
+            output: None,
+            next_ip: 2,
+        });
+        assert_eq!(trace.entries()[3].output, Some(2));
+        assert_eq!(trace.entries()[4].addrs, [None, None]);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let trace = record(&[2, -3, 0]);
+        let bytes = trace.to_bytes();
+
+        assert_eq!(Trace::from_bytes(&bytes).unwrap(), trace);
+
+        match Trace::from_bytes(&bytes[..bytes.len() - 1]) {
+            Err(TraceError::Malformed { offset, .. }) => assert_eq!(offset, bytes.len() - 1),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_traces() {
+        let header = |segment_addr: u64| {
+            let mut out = MAGIC.to_vec();
+            out.push(VERSION);
+            write_unsigned(&mut out, 0);
+            write_signed(&mut out, 0);
+            write_unsigned(&mut out, 1);
+            write_unsigned(&mut out, segment_addr);
+            write_values(&mut out, &[99, 0]);
+            write_values(&mut out, &[]);
+            write_values(&mut out, &[]);
+            out
+        };
+
+        let bytes = header(usize::MAX as u64 - 1);
+        match Trace::from_bytes(&bytes) {
+            Err(TraceError::Malformed { offset, .. }) => assert_eq!(offset, MAGIC.len() + 4),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Adjusting the base past isize::MAX is valid when overflow wraps
+        let mut vm = VM::new(vec![109, isize::MAX, 109, 1, 99]);
+        vm.set_overflow_policy(OverflowPolicy::Wrapping);
+        vm.start_recording();
+        vm.run().unwrap();
+        let trace = vm.stop_recording().unwrap();
+
+        assert_eq!(trace.entries()[1].next_relative_base, isize::MIN);
+        assert_eq!(Trace::from_bytes(&trace.to_bytes()).unwrap(), trace);
+    }
+
+    #[test]
+    fn replay_steps_both_ways() {
+        let trace = record(&[2, 3, 0]);
+        let mut replay = trace.replay();
+        let sum = 16;
+
+        replay.seek(9);
+        assert_eq!(replay.read(sum), 5);
+        assert_eq!(replay.outputs(), [2, 5]);
+        assert_eq!(replay.ip(), 11);
+
+        let entry = replay.step_back().unwrap();
+        assert_eq!(entry.output, Some(5));
+        assert_eq!(replay.outputs(), [2]);
+
+        replay.step_back();
+        assert_eq!(replay.read(sum), 2);
+
+        replay.seek(0);
+        assert_eq!(replay.read(sum), 0);
+        assert_eq!(replay.ip(), 0);
+
+        replay.seek(usize::MAX);
+        assert!(replay.is_at_end());
+    }
+
+    #[test]
+    fn finds_divergence() {
+        let trace = record(&[2, 3, 0]);
+
+        assert_eq!(trace.reexecute(), Ok(None));
+        assert_eq!(trace.divergence(&record(&[2, 4, 0])), Some(5));
+        assert_eq!(trace.divergence(&record(&[2, 0])), Some(5));
+
+        // Far writes from a paged VM must not allocate everything below them
+        let far = 1 << 40;
+        let mut vm = VM::with_memory(PagedMemory::new(&[1101, 2, 3, far, 4, far, 99]));
+        vm.step().unwrap();
+        vm.start_recording();
+        vm.run().unwrap();
+        let trace = vm.stop_recording().unwrap();
+
+        assert_eq!(trace.start().segments().len(), 2);
+        assert_eq!(trace.reexecute(), Ok(None));
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::{VM, VmError, Limit};
+
+// Checking the clock on every instruction would dominate tight loops
+const CLOCK_INTERVAL: u64 = 1024;
+// Loops longer than this many backward jumps go undetected, but memory
+// use stays bounded
+const SEEN_LIMIT: usize = 1 << 20;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Watchdog {
+    executed: u64,
+    instruction_limit: Option<u64>,
+    memory_limit: Option<usize>,
+    time_limit: Option<Duration>,
+    // Set by the first instruction executed in each run
+    deadline: Option<Instant>,
+    detect_loops: bool,
+    // Hashes of the states seen at backward jumps since the last input
+    seen: HashSet<u64>,
+}
+
+impl Watchdog {
+    pub(crate) fn detects_loops(&self) -> bool {
+        self.detect_loops
+    }
+
+    // Inputs can change what a loop does next, so earlier states no longer
+    // prove anything
+    pub(crate) fn forget_states(&mut self) {
+        self.seen.clear();
+    }
+}
+
+impl<'a, Context> VM<'a, Context> {
+    // Fails runs once `limit` more instructions have been executed
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.watchdog.instruction_limit = limit;
+        self.watchdog.executed = 0;
+    }
+
+    // Instructions executed since the instruction limit was last set
+    pub fn instructions_executed(&self) -> u64 {
+        self.watchdog.executed
+    }
+
+    // Fails writes by the program at or beyond `limit` cells
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.watchdog.memory_limit = limit;
+    }
+
+    // Fails runs that take longer than `limit`. The clock restarts with every
+    // call to `run`, `run_until_blocked` and `run_observed`, so a program
+    // resumed after each output gets `limit` per resume. `step` never restarts
+    // it, callers stepping by hand use `reset_time_limit` between runs.
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.watchdog.time_limit = limit;
+        self.watchdog.deadline = None;
+    }
+
+    // Gives the next instruction a fresh `limit` to run in
+    pub fn reset_time_limit(&mut self) {
+        self.watchdog.deadline = None;
+    }
+
+    // Fails runs that take a backward jump into a state (ip, relative base
+    // and memory) they were already in without reading input in between.
+    // Hashes the whole memory at every backward jump, so it is slow. Only the
+    // last million or so states are kept, so longer loops aren't caught.
+    pub fn set_loop_detection(&mut self, state: bool) {
+        self.watchdog.detect_loops = state;
+        self.watchdog.seen.clear();
+    }
+
+    // Runs `f` with at most `budget` more instructions on top of any limit
+    // the caller set, which is restored afterwards
+    pub(crate) fn with_instruction_budget<T>(&mut self, budget: u64, f: impl FnOnce(&mut Self) -> T) -> T {
+        let limit = self.watchdog.instruction_limit;
+        let budget_limit = self.watchdog.executed.saturating_add(budget);
+
+        self.watchdog.instruction_limit = Some(limit.map_or(budget_limit, |limit| limit.min(budget_limit)));
+        let result = f(self);
+        self.watchdog.instruction_limit = limit;
+
+        result
+    }
+
+    pub(crate) fn check_limits(&mut self) -> Result<(), VmError> {
+        let ip = self.ip;
+        let watchdog = &mut self.watchdog;
+        let exceeded = |limit| Err(VmError::LimitExceeded { ip, limit });
+
+        if watchdog.instruction_limit.is_some_and(|limit| watchdog.executed >= limit) {
+            return exceeded(Limit::Instructions);
+        }
+
+        if let Some(time_limit) = watchdog.time_limit {
+            let deadline = *watchdog.deadline.get_or_insert_with(|| Instant::now() + time_limit);
+
+            if watchdog.executed.is_multiple_of(CLOCK_INTERVAL) && Instant::now() >= deadline {
+                return exceeded(Limit::Time);
+            }
+        }
+
+        watchdog.executed += 1;
+
+        Ok(())
+    }
+
+    pub(crate) fn check_memory(&self, addr: usize) -> Result<(), VmError> {
+        match self.watchdog.memory_limit {
+            Some(limit) if addr >= limit => Err(VmError::LimitExceeded { ip: self.ip, limit: Limit::Memory }),
+            _ => Ok(()),
+        }
+    }
+
+    // Called after a backward jump from `ip`
+    pub(crate) fn check_loop(&mut self, ip: usize) -> Result<(), VmError> {
+        let mut hasher = DefaultHasher::new();
+        self.ip.hash(&mut hasher);
+        self.relative_base.hash(&mut hasher);
+
+        for (addr, cells) in self.mem.segments() {
+            // Trailing zeros from memory growth don't change the state
+            let len = cells.iter().rposition(|&value| value != 0).map_or(0, |index| index + 1);
+            addr.hash(&mut hasher);
+            cells[..len].hash(&mut hasher);
+        }
+
+        if self.watchdog.seen.len() >= SEEN_LIMIT {
+            self.watchdog.seen.clear();
+        }
+
+        if !self.watchdog.seen.insert(hasher.finish()) {
+            return Err(VmError::InfiniteLoop { ip });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::{VM, VmError, Limit, RunState};
+    use crate::asm::assemble;
+
+    const SPIN: &str = "
+        loop:
+            add [counter], #1, [counter]
+            jt #1, #loop
+        counter: .data 0
+    ";
+
+    #[test]
+    fn instruction_limit() {
+        let mut vm = VM::new(assemble(SPIN).unwrap());
+        vm.set_instruction_limit(Some(10));
+
+        assert_eq!(vm.run(), Err(VmError::LimitExceeded { ip: 0, limit: Limit::Instructions }));
+        assert_eq!(vm.instructions_executed(), 10);
+        assert_eq!(vm.read(7), 5);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut vm = VM::new(vec![1101, 1, 1, 99, 1101, 1, 1, 100, 99]);
+        vm.set_memory_limit(Some(100));
+
+        assert_eq!(vm.run(), Err(VmError::LimitExceeded { ip: 4, limit: Limit::Memory }));
+        assert_eq!(vm.read(99), 2);
+    }
+
+    #[test]
+    fn time_limit() {
+        let mut vm = VM::new(assemble(SPIN).unwrap());
+        vm.set_time_limit(Some(Duration::from_millis(20)));
+
+        let err = vm.run().unwrap_err();
+        assert!(matches!(err, VmError::LimitExceeded { limit: Limit::Time, .. }));
+
+        // Waiting for input between runs doesn't count against the limit
+        let mut vm = VM::new(assemble("
+                out #1
+                in [counter]
+            loop:
+                add [counter], #-1, [counter]
+                jt [counter], #loop
+                hlt
+            counter: .data 0
+        ").unwrap());
+        vm.set_time_limit(Some(Duration::from_millis(20)));
+
+        assert_eq!(vm.run_until_blocked(), Ok(RunState::Output(1)));
+        thread::sleep(Duration::from_millis(40));
+        vm.add_input(5000);
+        assert_eq!(vm.run_until_blocked(), Ok(RunState::Halted));
+
+        // Stepping keeps one deadline until it is reset
+        let mut vm = VM::new(assemble(SPIN).unwrap());
+        vm.set_time_limit(Some(Duration::from_millis(20)));
+        vm.step().unwrap();
+        thread::sleep(Duration::from_millis(40));
+
+        let result = (0..2000).try_for_each(|_| vm.step().map(drop));
+        assert!(matches!(result, Err(VmError::LimitExceeded { limit: Limit::Time, .. })));
+
+        vm.reset_time_limit();
+        assert_eq!((0..2000).try_for_each(|_| vm.step().map(drop)), Ok(()));
+    }
+
+    #[test]
+    fn loop_detection() {
+        // Changes memory on every iteration, so it only stops at the limit
+        let mut vm = VM::new(assemble(SPIN).unwrap());
+        vm.set_loop_detection(true);
+        vm.set_instruction_limit(Some(1000));
+        assert!(matches!(vm.run(), Err(VmError::LimitExceeded { .. })));
+
+        let mut vm = VM::new(assemble("
+            loop:
+                in [value]
+                jf [value], #stuck
+                jt #1, #loop
+            stuck:
+                add #0, #0, [value]
+                jt #1, #stuck
+            value: .data 0
+        ").unwrap());
+        vm.set_loop_detection(true);
+
+        // Reading the same input again is progress, only the input-free
+        // loop at the end is stuck
+        for input in [3, 3, 3, 0] {
+            vm.add_input(input);
+        }
+
+        assert_eq!(vm.run(), Err(VmError::InfiniteLoop { ip: 12 }));
+    }
+}
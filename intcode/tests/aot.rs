@@ -0,0 +1,98 @@
+use intcode::{VM, RunState};
+use intcode::aot::{self, Machine};
+use intcode::asm::assemble;
+
+#[path = "aot/squares.rs"]
+mod squares;
+
+// Sums the squares of its inputs up to a 0, calling a subroutine through the
+// relative base. `bump` is patched by a plain write and `patched` through the
+// relative base, so both run interpreted once they change.
+const SQUARES: &str = "
+        add #4, #0, rel[patched]
+        arb #stack
+    loop:
+        in [n]
+        jf [n], #done
+        add #ret, #0, rel[+0]
+        jt #1, #square
+    ret:
+        out [sq]
+        add [sq], #0, [bump_arg]
+    bump: .data 1001, total
+    bump_arg: .data 0, total
+        jt #1, #loop
+    square:
+        mul [n], [n], [sq]
+        jt #1, rel[+0]
+    done:
+        out [total]
+    patched: out #1
+        hlt
+    n: .data 0
+    sq: .data 0
+    total: .data 0
+    stack: .zero 4
+";
+
+#[test]
+fn generated_code_is_current() {
+    let program = assemble(SQUARES).unwrap();
+    assert_eq!(aot::compile(&program), include_str!("aot/squares.rs"));
+}
+
+#[test]
+fn matches_vm() {
+    let program = assemble(SQUARES).unwrap();
+    let inputs: &[&[isize]] = &[
+        &[0],
+        &[3, 0],
+        &[1, 2, 3, 4, 0],
+        &[-5, 7, -11, 0],
+        &[1 << 40, 0],
+    ];
+
+    for inputs in inputs {
+        let mut vm = VM::new(program.clone());
+        let mut machine = Machine::new(program.clone(), squares::CODE);
+
+        for &input in inputs.iter() {
+            vm.add_input(input);
+            machine.add_input(input);
+        }
+
+        let expected = vm.run().map(|()| RunState::Halted);
+        assert_eq!(machine.run(squares::run), expected, "inputs {:?}", inputs);
+        assert_eq!(machine.outputs(), vm.outputs(), "inputs {:?}", inputs);
+        assert_eq!(machine.ip(), vm.ip());
+        assert_eq!(machine.relative_base(), vm.relative_base());
+
+        for addr in 0..machine.mem().len() {
+            assert_eq!(machine.read(addr), vm.read(addr), "memory differs at {}", addr);
+        }
+    }
+}
+
+#[test]
+fn resumes_after_input() {
+    let program = assemble(SQUARES).unwrap();
+    let mut machine = Machine::new(program.clone(), squares::CODE);
+
+    machine.add_input(2);
+    assert_eq!(machine.run(squares::run), Ok(RunState::NeedsInput));
+    assert_eq!(machine.outputs(), [4]);
+
+    machine.add_input(3);
+    machine.add_input(0);
+    assert_eq!(machine.run(squares::run), Ok(RunState::Halted));
+    // The last output comes from `patched` after it became `out [1]`
+    assert_eq!(machine.outputs(), [4, 9, 13, 4]);
+
+    let mut vm = VM::new(program);
+    vm.add_input(2);
+    vm.add_input(3);
+    vm.add_input(0);
+    vm.run().unwrap();
+
+    assert_eq!(machine.outputs(), vm.outputs());
+}
@@ -0,0 +1,125 @@
+// Generated by intcode::aot from a 50 cell program, do not edit.
+
+use intcode::VmError;
+use intcode::aot::{Machine, Exit};
+
+pub const CODE: &[(usize, usize)] = &[
+    (0, 4),
+    (4, 2),
+    (6, 2),
+    (8, 3),
+    (11, 4),
+    (15, 3),
+    (18, 2),
+    (20, 4),
+    (28, 3),
+    (31, 4),
+    (35, 3),
+    (38, 2),
+    (40, 2),
+    (42, 1),
+];
+
+pub fn run(m: &mut Machine) -> Result<Exit, VmError> {
+    loop {
+        let ip = m.ip();
+
+        if !m.is_compiled(ip) {
+            return Ok(Exit::Interpret);
+        }
+
+        match ip {
+            // add #4, #0, rel[+40]
+            0 => {
+                let a: isize = 4;
+                let b: isize = 0;
+                let value = a.checked_add(b).ok_or(VmError::Overflow { ip: 0 })?;
+                m.write(m.relative(0, 40)?, value);
+                m.set_ip(4);
+            },
+            // arb #46
+            4 => {
+                let offset = 46;
+                m.adjust_relative_base(4, offset)?;
+                m.set_ip(6);
+            },
+            // in [43]
+            6 => {
+                let value = match m.pop_input() {
+                    Some(value) => value,
+                    None => return Ok(Exit::NeedsInput),
+                };
+                m.write(43, value);
+                m.set_ip(8);
+            },
+            // jf [43], #38
+            8 => {
+                if m.read(43) == 0 {
+                    m.set_ip(38);
+                } else {
+                    m.set_ip(11);
+                }
+            },
+            // add #18, #0, rel[+0]
+            11 => {
+                let a: isize = 18;
+                let b: isize = 0;
+                let value = a.checked_add(b).ok_or(VmError::Overflow { ip: 11 })?;
+                m.write(m.relative(11, 0)?, value);
+                m.set_ip(15);
+            },
+            // jt #1, #31
+            15 => {
+                m.set_ip(31);
+            },
+            // out [44]
+            18 => {
+                let value = m.read(44);
+                m.push_output(value);
+                m.set_ip(20);
+            },
+            // add [44], #0, [26]
+            20 => {
+                let a: isize = m.read(44);
+                let b: isize = 0;
+                let value = a.checked_add(b).ok_or(VmError::Overflow { ip: 20 })?;
+                m.write(26, value);
+                m.set_ip(24);
+            },
+            // jt #1, #6
+            28 => {
+                m.set_ip(6);
+            },
+            // mul [43], [43], [44]
+            31 => {
+                let a: isize = m.read(43);
+                let b: isize = m.read(43);
+                let value = a.checked_mul(b).ok_or(VmError::Overflow { ip: 31 })?;
+                m.write(44, value);
+                m.set_ip(35);
+            },
+            // jt #1, rel[+0]
+            35 => {
+                let target = m.read(m.relative(35, 0)?);
+                m.set_ip(m.addr(35, target)?);
+            },
+            // out [45]
+            38 => {
+                let value = m.read(45);
+                m.push_output(value);
+                m.set_ip(40);
+            },
+            // out #1
+            40 => {
+                let value = 1;
+                m.push_output(value);
+                m.set_ip(42);
+            },
+            // hlt
+            42 => {
+                return Ok(Exit::Halted);
+            },
+            _ => return Ok(Exit::Interpret),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::fs;
+use std::path::Path;
+
+use intcode::{VM, FastVM, PagedMemory, RunState, VmError};
+use intcode::aot::{Machine, Exit};
+
+// A case from tests/conformance. Every case must produce exactly `outputs`
+// and end with `error`, or halt when it is None. `memory` lists expected
+// runs of cells by start address.
+struct Case {
+    name: String,
+    program: Vec<isize>,
+    inputs: Vec<isize>,
+    outputs: Vec<isize>,
+    memory: Vec<(usize, Vec<isize>)>,
+    error: Option<String>,
+}
+
+struct Finished {
+    result: Result<(), VmError>,
+    outputs: Vec<isize>,
+    mem: Vec<isize>,
+}
+
+// Anything that can run Intcode. Inputs are all available up front.
+trait Backend {
+    fn run(&self, program: &[isize], inputs: &[isize]) -> Finished;
+}
+
+// The VM over dense memory, as `VM::new` builds it
+struct Interpreter;
+
+impl Backend for Interpreter {
+    fn run(&self, program: &[isize], inputs: &[isize]) -> Finished {
+        run_vm(VM::new(program), inputs)
+    }
+}
+
+// The VM over paged memory, which stores sparse high addresses differently
+struct Paged;
+
+impl Backend for Paged {
+    fn run(&self, program: &[isize], inputs: &[isize]) -> Finished {
+        run_vm(VM::with_memory(PagedMemory::new(program)), inputs)
+    }
+}
+
+fn run_vm(mut vm: VM, inputs: &[isize]) -> Finished {
+    for &input in inputs {
+        vm.add_input(input);
+    }
+
+    let result = vm.run();
+    let mut mem = Vec::new();
+
+    for (start, cells) in vm.snapshot().segments() {
+        let end = start + cells.len();
+        mem.resize(mem.len().max(end), 0);
+        mem[*start..end].copy_from_slice(cells);
+    }
+
+    Finished { result, outputs: vm.outputs().to_vec(), mem }
+}
+
+struct Fast;
+
+impl Backend for Fast {
+    fn run(&self, program: &[isize], inputs: &[isize]) -> Finished {
+        let mut vm = FastVM::new(program);
+
+        for &input in inputs {
+            vm.add_input(input);
+        }
+
+        let result = match vm.run() {
+            Ok(RunState::NeedsInput) => Err(VmError::InputExhausted { ip: vm.ip() }),
+            result => result.map(|_| ()),
+        };
+
+        Finished { result, outputs: vm.outputs().to_vec(), mem: vm.mem().to_vec() }
+    }
+}
+
+// Only the ahead-of-time runtime's fallback interpreter: no code is
+// compiled, so every instruction is interpreted. Generated code is
+// covered by tests/aot.rs instead.
+struct AotInterpreter;
+
+impl Backend for AotInterpreter {
+    fn run(&self, program: &[isize], inputs: &[isize]) -> Finished {
+        let mut machine = Machine::new(program, &[]);
+
+        for &input in inputs {
+            machine.add_input(input);
+        }
+
+        let result = match machine.run(|_| Ok(Exit::Interpret)) {
+            Ok(RunState::NeedsInput) => Err(VmError::InputExhausted { ip: machine.ip() }),
+            result => result.map(|_| ()),
+        };
+
+        Finished { result, outputs: machine.outputs().to_vec(), mem: machine.mem().to_vec() }
+    }
+}
+
+fn parse_values(values: &str) -> Vec<isize> {
+    values
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse().unwrap_or_else(|_| panic!("invalid value `{}`", value)))
+        .collect()
+}
+
+fn parse_cases(source: &str) -> Vec<Case> {
+    let mut cases: Vec<Case> = Vec::new();
+
+    for line in source.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let (key, value) = line.split_once(':').unwrap_or_else(|| panic!("invalid line `{}`", line));
+        let value = value.trim();
+
+        if key == "case" {
+            cases.push(Case {
+                name: value.to_owned(),
+                program: Vec::new(),
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+                memory: Vec::new(),
+                error: None,
+            });
+            continue;
+        }
+
+        let case = cases.last_mut().unwrap_or_else(|| panic!("`{}` outside of a case", line));
+
+        match key {
+            "program" => case.program = parse_values(value),
+            "input" => case.inputs = parse_values(value),
+            "output" => case.outputs = parse_values(value),
+            "error" => case.error = Some(value.to_owned()),
+            _ => {
+                let addr = key
+                    .strip_prefix("memory[")
+                    .and_then(|key| key.strip_suffix(']'))
+                    .and_then(|addr| addr.parse().ok())
+                    .unwrap_or_else(|| panic!("unknown key `{}`", key));
+
+                case.memory.push((addr, parse_values(value)));
+            },
+        }
+    }
+
+    cases
+}
+
+fn load_cases() -> Vec<(String, Case)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance");
+    let mut paths = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .flat_map(|path| {
+            let file = path.file_name().unwrap().to_string_lossy().into_owned();
+            let source = fs::read_to_string(&path).unwrap();
+
+            parse_cases(&source).into_iter().map(move |case| (file.clone(), case))
+        })
+        .collect()
+}
+
+fn check(backend: &dyn Backend, case: &Case) -> Result<(), String> {
+    let finished = backend.run(&case.program, &case.inputs);
+    let error = finished.result.err().map(|err| err.to_string());
+
+    if error != case.error {
+        return Err(format!("expected {:?}, got {:?}", case.error, error));
+    }
+
+    if finished.outputs != case.outputs {
+        return Err(format!("expected outputs {:?}, got {:?}", case.outputs, finished.outputs));
+    }
+
+    for (start, cells) in &case.memory {
+        let actual = (*start..start + cells.len())
+            .map(|addr| finished.mem.get(addr).copied().unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        if &actual != cells {
+            return Err(format!("expected memory[{}] {:?}, got {:?}", start, cells, actual));
+        }
+    }
+
+    Ok(())
+}
+
+fn assert_conforms(backend: &dyn Backend) {
+    let cases = load_cases();
+    assert!(!cases.is_empty());
+
+    let failures = cases
+        .iter()
+        .filter_map(|(file, case)| {
+            check(backend, case)
+                .err()
+                .map(|message| format!("{}: {}: {}", file, case.name, message))
+        })
+        .collect::<Vec<_>>();
+
+    assert!(failures.is_empty(), "{} of {} cases failed:\n{}", failures.len(), cases.len(), failures.join("\n"));
+}
+
+#[test]
+fn vm_conforms() {
+    assert_conforms(&Interpreter);
+}
+
+#[test]
+fn paged_vm_conforms() {
+    assert_conforms(&Paged);
+}
+
+#[test]
+fn fast_vm_conforms() {
+    assert_conforms(&Fast);
+}
+
+#[test]
+fn aot_interpreter_conforms() {
+    assert_conforms(&AotInterpreter);
+}